@@ -0,0 +1,65 @@
+use std::env;
+
+use crate::controller::emulated::ControllerModel;
+use crate::error::{Error, Result};
+
+const USAGE: &str = "Usage: ddgo-pnp-controller [OPTIONS]
+
+Options:
+  --model <MODEL>    Emulate MODEL instead of reading the buttons held at boot
+  --list-models      List the supported models and exit
+  --input <PATH>     Read the controller from PATH, can be repeated
+  --no-stop-game     Leave the main game running
+  --dry-run          Select the model without setting up the gadget
+  --help             Show this help and exit";
+
+/// Options given on the command line.
+#[derive(Default)]
+pub struct Options {
+    /// Model forced with `--model`.
+    pub model: Option<ControllerModel>,
+    /// List the supported models instead of running.
+    pub list_models: bool,
+    /// Input devices replacing the configured ones.
+    pub inputs: Vec<String>,
+    /// Stop the main game once a model is selected.
+    pub stop_game: bool,
+    /// Only report the selected model.
+    pub dry_run: bool,
+    /// Show the usage instead of running.
+    pub help: bool,
+}
+
+/// Parse the command line arguments.
+pub fn parse() -> Result<Options> {
+    let mut options = Options {
+        stop_game: true,
+        ..Default::default()
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--model" => {
+                options.model = Some(value(&arg, args.next())?.parse().map_err(|e| match e {
+                    Error::Config(message) => Error::Usage(message),
+                    e => e,
+                })?)
+            }
+            "--list-models" => options.list_models = true,
+            "--input" => options.inputs.push(value(&arg, args.next())?),
+            "--no-stop-game" => options.stop_game = false,
+            "--dry-run" => options.dry_run = true,
+            "-h" | "--help" => options.help = true,
+            _ => return Err(Error::Usage(format!("unknown option '{}'", arg))),
+        }
+    }
+    Ok(options)
+}
+
+pub fn print_usage() {
+    println!("{}", USAGE);
+}
+
+fn value(option: &str, value: Option<String>) -> Result<String> {
+    value.ok_or_else(|| Error::Usage(format!("missing value for '{}'", option)))
+}
@@ -0,0 +1,279 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::controller::emulated::ControllerModel;
+use crate::error::{Error, Result};
+
+const CONFIG_FILE: &str = "/etc/ddgo-pnp-controller.conf";
+
+/// Shortest selection wait and hold time, as the input is polled at this interval.
+const MIN_WAIT: Duration = Duration::from_millis(100);
+
+/// Settings read from the configuration file.
+pub struct Config {
+    /// Time SELECT must be pressed to count as held.
+    pub hold_delay: Duration,
+    /// Time given to the user to press a button before the model is selected.
+    pub selection_wait: Duration,
+    /// Time SELECT and START must be held together to switch the model.
+    pub switch_hold: Duration,
+    /// Time SELECT and START must be held together to leave the game for controller mode.
+    pub watch_hold: Duration,
+    /// Interval to resend unchanged reports, `None` to only send them on change.
+    pub keep_alive: Option<Duration>,
+    /// Sysfs path of the door lamp LED.
+    pub lamp_led: String,
+    /// Sysfs path of the rumble LED.
+    pub rumble_led: String,
+    /// Input devices of the physical controller, discovered if empty.
+    pub inputs: Vec<String>,
+    /// Model emulated when no button is pressed, `None` to start the RNDIS gadget.
+    pub default_model: Option<ControllerModel>,
+    /// Emulate the last selected model when no button is pressed, before the default model.
+    pub remember_model: bool,
+    /// File holding the last selected model.
+    pub state_file: String,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            hold_delay: Duration::from_millis(750),
+            selection_wait: Duration::from_secs(3),
+            switch_hold: Duration::from_secs(3),
+            watch_hold: Duration::from_secs(5),
+            keep_alive: None,
+            lamp_led: String::from("/sys/class/leds/led2"),
+            rumble_led: String::from("/sys/class/leds/led1"),
+            inputs: Vec::new(),
+            default_model: None,
+            remember_model: false,
+            state_file: String::from("/etc/ddgo-pnp-controller.state"),
+        }
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Load the configuration file, or the file set in `DDGO_PNP_CONFIG`.
+///
+/// The file holds `key = value` lines, with `#` starting a comment. Durations are given
+/// in milliseconds. A missing file leaves every setting at its default.
+pub fn init() -> Result<()> {
+    let path = env::var("DDGO_PNP_CONFIG").unwrap_or_else(|_| String::from(CONFIG_FILE));
+    let mut config = Config::default();
+    match fs::read_to_string(&path) {
+        Ok(contents) => parse(&mut config, &contents).map_err(|e| match e {
+            Error::Config(message) => Error::Config(format!("{}: {}", path, message)),
+            e => e,
+        })?,
+        Err(e) if e.kind() == ErrorKind::NotFound => (),
+        Err(e) => return Err(Error::Config(format!("{}: {}", path, e))),
+    }
+    // Settings read before would silently stay at their defaults
+    CONFIG
+        .set(config)
+        .map_err(|_| Error::Setup(format!("{}: settings were used before loading", path)))
+}
+
+/// Current settings, the defaults until the configuration is loaded.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+fn parse(config: &mut Config, contents: &str) -> Result<()> {
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if !line.is_empty() {
+            parse_line(config, line).map_err(|e| match e {
+                Error::Config(message) => {
+                    Error::Config(format!("line {}: {}", number + 1, message))
+                }
+                e => e,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_line(config: &mut Config, line: &str) -> Result<()> {
+    let (key, value) = line
+        .split_once('=')
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .ok_or_else(|| Error::Config(String::from("expected 'key = value'")))?;
+    match key {
+        "hold_delay" => config.hold_delay = parse_duration(key, value)?,
+        "selection_wait" => config.selection_wait = parse_wait(key, value)?,
+        "switch_hold" => config.switch_hold = parse_wait(key, value)?,
+        "watch_hold" => config.watch_hold = parse_wait(key, value)?,
+        "keep_alive" => config.keep_alive = parse_keep_alive(value)?,
+        "lamp_led" => config.lamp_led = String::from(value),
+        "rumble_led" => config.rumble_led = String::from(value),
+        "input" => config.inputs.push(String::from(value)),
+        "default_model" => {
+            config.default_model = match value {
+                "" | "none" => None,
+                _ => Some(value.parse()?),
+            }
+        }
+        "remember_model" => config.remember_model = parse_bool(key, value)?,
+        "state_file" => config.state_file = String::from(value),
+        _ => return Err(Error::Config(format!("unknown key '{}'", key))),
+    }
+    Ok(())
+}
+
+fn parse_duration(key: &str, value: &str) -> Result<Duration> {
+    value
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|_| Error::Config(format!("{} must be a number of milliseconds", key)))
+}
+
+fn parse_wait(key: &str, value: &str) -> Result<Duration> {
+    let wait = parse_duration(key, value)?;
+    if wait < MIN_WAIT {
+        return Err(Error::Config(format!(
+            "{} must be at least {} milliseconds",
+            key,
+            MIN_WAIT.as_millis()
+        )));
+    }
+    Ok(wait)
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(Error::Config(format!("{} must be true or false", key))),
+    }
+}
+
+fn parse_keep_alive(value: &str) -> Result<Option<Duration>> {
+    let interval = parse_duration("keep_alive", value)?;
+    Ok(Some(interval).filter(|interval| !interval.is_zero()))
+}
+
+/// Last selected model saved in the state file, if it is remembered.
+pub fn last_model() -> Option<ControllerModel> {
+    let config = get();
+    if !config.remember_model {
+        return None;
+    }
+    let id = fs::read_to_string(&config.state_file).ok()?;
+    match id.trim().parse() {
+        Ok(model) => Some(model),
+        Err(e) => {
+            warn!("Ignoring {}: {}", config.state_file, e);
+            None
+        }
+    }
+}
+
+/// Save the selected model to the state file, if it is remembered.
+pub fn save_model(model: ControllerModel) {
+    let config = get();
+    if config.remember_model
+        && last_model() != Some(model)
+        && let Err(e) = write_state(&config.state_file, &format!("{}\n", model.id()))
+    {
+        warn!("Could not save model to {}: {}", config.state_file, e);
+    }
+}
+
+/// Write the state file, remounting the root filesystem read-write for it if needed.
+fn write_state(path: &str, contents: &str) -> io::Result<()> {
+    match fs::write(path, contents) {
+        Err(e) if e.kind() == ErrorKind::ReadOnlyFilesystem => {
+            remount_root("rw")?;
+            let result = fs::write(path, contents);
+            if let Err(e) = remount_root("ro") {
+                warn!("Could not remount root filesystem read-only: {}", e);
+            }
+            result
+        }
+        result => result,
+    }
+}
+
+fn remount_root(mode: &str) -> io::Result<()> {
+    let output = Command::new("mount")
+        .args(["-o", &format!("remount,{}", mode), "/"])
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_settings() {
+        let mut config = Config::default();
+        parse(
+            &mut config,
+            "# Settings\n\
+             selection_wait = 500\n\
+             \n\
+             keep_alive=0 # only on change\n\
+             input = /dev/input/event0\n\
+             input = /dev/input/event1\n\
+             default_model = tcpp-20009\n\
+             remember_model = true\n",
+        )
+        .unwrap();
+        assert_eq!(config.selection_wait, Duration::from_millis(500));
+        assert_eq!(config.keep_alive, None);
+        assert_eq!(config.inputs, ["/dev/input/event0", "/dev/input/event1"]);
+        assert_eq!(
+            config.default_model.map(|model| model.id()),
+            Some("TCPP-20009")
+        );
+        assert!(config.remember_model);
+        assert_eq!(config.switch_hold, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn parse_keep_alive_interval() {
+        let mut config = Config::default();
+        parse(&mut config, "keep_alive = 40").unwrap();
+        assert_eq!(config.keep_alive, Some(Duration::from_millis(40)));
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        for (contents, message) in [
+            ("hold_delay", "line 1: expected 'key = value'"),
+            (
+                "\nhold_delay = soon",
+                "line 2: hold_delay must be a number of milliseconds",
+            ),
+            (
+                "remember_model = yes",
+                "line 1: remember_model must be true or false",
+            ),
+            (
+                "default_model = abc",
+                "line 1: unknown controller model 'abc'",
+            ),
+            ("colour = red", "line 1: unknown key 'colour'"),
+        ] {
+            match parse(&mut Config::default(), contents) {
+                Err(Error::Config(error)) => assert_eq!(error, message),
+                _ => panic!("'{}' should be rejected", contents),
+            }
+        }
+    }
+}
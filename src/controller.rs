@@ -1,2 +1,3 @@
 pub mod emulated;
+pub mod gadget;
 pub mod physical;
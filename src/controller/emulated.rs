@@ -1,15 +1,27 @@
-use std::fs;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::fmt;
+use std::io::ErrorKind;
+use std::os::unix::thread::JoinHandleExt;
 use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Once};
 use std::thread;
+use std::thread::JoinHandle;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::controller::physical::ControllerState;
+use bitflags::bitflags;
+use nix::libc::c_int;
+use nix::sys::pthread::pthread_kill;
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+
+use crate::config;
+use crate::controller::gadget::{ControlRequest, Event, Gadget, GadgetState};
+use crate::controller::physical::{ControllerState, Pattern, set_lamp, set_rumble};
+use crate::error::{Error, Result};
 
 mod dgoc44u;
+mod generic;
 mod slph00051;
 mod sotp031201_p4b2b7;
 mod sotp031201_p4b7;
@@ -21,337 +33,1058 @@ mod tcpp20004;
 mod tcpp20009;
 mod tcpp20011;
 mod zkns001;
-mod generic;
 
-const FFS_MOUNT: &str = "/tmp/ffs";
-const ENDPOINT0: &str = "/tmp/ffs/ep0";
-const ENDPOINT1: &str = "/tmp/ffs/ep1";
-const ANDROID_GADGET: &str = "/sys/class/android_usb/android0";
-
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub enum ControllerModel {
-    DGOC44U,
-    SLPH00051,
-    SOTP031201P4B7,
-    SOTP031201P4B2B7,
-    SOTP031201P5B5,
-    SOTP031201P5B7,
-    TC5175290,
-    TCPP20003,
-    TCPP20004,
-    TCPP20009,
-    TCPP20011,
-    ZKNS001,
-    GENERIC,
+/// A supported controller model, referring to its entry in `CONTROLLERS`.
+#[derive(Clone, Copy)]
+pub struct ControllerModel(&'static dyn EmulatedController);
+
+impl ControllerModel {
+    /// Short identifier used in settings, matching the product code of the model.
+    pub fn id(&self) -> &'static str {
+        self.0.info().id
+    }
+
+    /// Human-readable model name.
+    pub fn name(&self) -> &'static str {
+        self.0.info().name
+    }
+
+    /// Feedback played once the model is set up.
+    pub fn confirmation(&self) -> &'static Pattern {
+        &self.0.info().confirmation
+    }
+}
+
+impl PartialEq for ControllerModel {
+    fn eq(&self, other: &ControllerModel) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl fmt::Debug for ControllerModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id())
+    }
+}
+
+impl FromStr for ControllerModel {
+    type Err = Error;
+
+    fn from_str(id: &str) -> Result<ControllerModel> {
+        models()
+            .find(|model| model.id().eq_ignore_ascii_case(id))
+            .ok_or_else(|| Error::Config(format!("unknown controller model '{}'", id)))
+    }
 }
 
 pub struct DeviceDescriptor {
-    b_device_class: u8,
-    b_device_sub_class: u8,
-    id_vendor: u16,
-    id_product: u16,
-    bcd_device: u16,
-    i_manufacturer: &'static str,
-    i_product: &'static str,
-    i_serial_number: &'static str,
-}
-
-pub fn set_model(state: &ControllerState) -> Option<ControllerModel> {
-    let model;
-    let model_name;
-    let descriptors: (&DeviceDescriptor, &[u8], &[u8]);
-    if state.button_right {
-        model_name = "DGOC44-U";
-        model = ControllerModel::DGOC44U;
-        descriptors = (
-            &dgoc44u::DEVICE_DESCRIPTOR,
-            &dgoc44u::DESCRIPTORS,
-            &dgoc44u::STRINGS,
-        );
-    } else if state.button_up {
-        model_name = "ZKNS-001";
-        model = ControllerModel::ZKNS001;
-        descriptors = (
-            &zkns001::DEVICE_DESCRIPTOR,
-            &zkns001::DESCRIPTORS,
-            &zkns001::STRINGS,
-        );
-    } else if state.button_down && state.power == 0 {
-        model_name = "SLPH-00051";
-        model = ControllerModel::SLPH00051;
-        descriptors = (
-            &slph00051::DEVICE_DESCRIPTOR,
-            &slph00051::DESCRIPTORS,
-            &slph00051::STRINGS,
-        );
-    } else if state.button_down && state.power == 1 {
-        model_name = "TCPP-20003";
-        model = ControllerModel::TCPP20003;
-        descriptors = (
-            &tcpp20003::DEVICE_DESCRIPTOR,
-            &tcpp20003::DESCRIPTORS,
-            &tcpp20003::STRINGS,
-        );
-    } else if state.button_down && state.power == 2 {
-        model_name = "TC-5175290";
-        model = ControllerModel::TC5175290;
-        descriptors = (
-            &tc5175290::DEVICE_DESCRIPTOR,
-            &tc5175290::DESCRIPTORS,
-            &tc5175290::STRINGS,
-        );
-    } else if state.button_down && state.power == 3 {
-        model_name = "TCPP-20004";
-        model = ControllerModel::TCPP20004;
-        descriptors = (
-            &tcpp20004::DEVICE_DESCRIPTOR,
-            &tcpp20004::DESCRIPTORS,
-            &tcpp20004::STRINGS,
-        );
-    } else if state.button_d {
-        model_name = "TCPP-20009";
-        model = ControllerModel::TCPP20009;
-        descriptors = (
-            &tcpp20009::DEVICE_DESCRIPTOR,
-            &tcpp20009::DESCRIPTORS,
-            &tcpp20009::STRINGS,
-        );
-    } else if state.button_a {
-        model_name = "Generic Train Controller";
-        model = ControllerModel::GENERIC;
-        descriptors = (
-            &generic::DEVICE_DESCRIPTOR,
-            &generic::DESCRIPTORS,
-            &generic::STRINGS,
-        );
-    } else if state.button_b {
-        model_name = "TCPP-20011";
-        model = ControllerModel::TCPP20011;
-        descriptors = (
-            &tcpp20011::DEVICE_DESCRIPTOR,
-            &tcpp20011::DESCRIPTORS,
-            &tcpp20011::STRINGS,
-        );
-    } else if state.button_c && state.power == 0 {
-        model_name = "SOTP-031201 (P4/B7 mode)";
-        model = ControllerModel::SOTP031201P4B7;
-        descriptors = (
-            &sotp031201_p4b7::DEVICE_DESCRIPTOR,
-            &sotp031201_p4b7::DESCRIPTORS,
-            &sotp031201_p4b7::STRINGS,
-        );
-    } else if state.button_c && state.power == 1 {
-        model_name = "SOTP-031201 (P4/B2-B7 mode)";
-        model = ControllerModel::SOTP031201P4B2B7;
-        descriptors = (
-            &sotp031201_p4b2b7::DEVICE_DESCRIPTOR,
-            &sotp031201_p4b2b7::DESCRIPTORS,
-            &sotp031201_p4b2b7::STRINGS,
-        );
-    } else if state.button_c && state.power == 2 {
-        model_name = "SOTP-031201 (P5/B5 mode)";
-        model = ControllerModel::SOTP031201P5B5;
-        descriptors = (
-            &sotp031201_p5b5::DEVICE_DESCRIPTOR,
-            &sotp031201_p5b5::DESCRIPTORS,
-            &sotp031201_p5b5::STRINGS,
-        );
-    } else if state.button_c && state.power == 3 {
-        model_name = "SOTP-031201 (P5/B7 mode)";
-        model = ControllerModel::SOTP031201P5B7;
-        descriptors = (
-            &sotp031201_p5b7::DEVICE_DESCRIPTOR,
-            &sotp031201_p5b7::DESCRIPTORS,
-            &sotp031201_p5b7::STRINGS,
-        );
-    } else {
-        println!("ddgo-pnp-controller: No controller selected, starting RNDIS gadget.");
-        Command::new("rndis-gadget.sh").output().ok();
-        return None;
+    pub b_device_class: u8,
+    pub b_device_sub_class: u8,
+    pub id_vendor: u16,
+    pub id_product: u16,
+    pub bcd_device: u16,
+    pub i_manufacturer: &'static str,
+    pub i_product: &'static str,
+    pub i_serial_number: &'static str,
+}
+
+bitflags! {
+    /// Buttons of the Plug & Play that can be part of a selection chord.
+    pub struct ChordButtons: u16 {
+        const SELECT = 1;
+        const START = 2;
+        const A = 4;
+        const B = 8;
+        const C = 16;
+        const D = 32;
+        const UP = 64;
+        const DOWN = 128;
+        const LEFT = 256;
+        const RIGHT = 512;
     }
-    println!("ddgo-pnp-controller: Selected controller {}.", model_name);
-    init_gadget(&model, descriptors);
-    return Some(model);
 }
 
-pub fn set_state(state: &mut ControllerState, model: &ControllerModel) {
-    match model {
-        ControllerModel::DGOC44U => {
-            dgoc44u::update_gadget(state);
+impl ChordButtons {
+    /// Buttons pressed in the state.
+    fn held(state: &ControllerState) -> ChordButtons {
+        let mut buttons = ChordButtons::empty();
+        buttons.set(ChordButtons::SELECT, state.button_select);
+        buttons.set(ChordButtons::START, state.button_start);
+        buttons.set(ChordButtons::A, state.button_a);
+        buttons.set(ChordButtons::B, state.button_b);
+        buttons.set(ChordButtons::C, state.button_c);
+        buttons.set(ChordButtons::D, state.button_d);
+        buttons.set(ChordButtons::UP, state.button_up);
+        buttons.set(ChordButtons::DOWN, state.button_down);
+        buttons.set(ChordButtons::LEFT, state.button_left);
+        buttons.set(ChordButtons::RIGHT, state.button_right);
+        buttons
+    }
+}
+
+/// Controls held at boot to select a model. Buttons not in the chord are not checked.
+pub struct Chord {
+    /// Buttons that must be pressed.
+    pub buttons: ChordButtons,
+    /// Power notch the handle must be at, if any.
+    pub power: Option<u8>,
+    /// Brake notch the handle must be at, if any.
+    pub brake: Option<u8>,
+}
+
+impl Chord {
+    fn matches(&self, state: &ControllerState) -> bool {
+        ChordButtons::held(state).contains(self.buttons)
+            && self.power.is_none_or(|power| power == state.power)
+            && self.brake.is_none_or(|brake| brake == state.brake)
+    }
+
+    /// Whether every input matching `other` also matches this chord.
+    fn covers(&self, other: &Chord) -> bool {
+        other.buttons.contains(self.buttons)
+            && self.power.is_none_or(|power| other.power == Some(power))
+            && self.brake.is_none_or(|brake| other.brake == Some(brake))
+    }
+}
+
+/// Result of reading the selection chord.
+pub enum Selection {
+    /// The held controls match the chord of a single model.
+    Model(ControllerModel),
+    /// No chord is held.
+    None,
+    /// The RNDIS chord is held.
+    Rndis,
+    /// The menu chord is held.
+    Menu,
+    /// The held controls match several chords, named in the list.
+    Ambiguous(Vec<&'static str>),
+}
+
+/// Answer to a control request.
+pub enum Control {
+    /// Send the data back to the host.
+    Reply(Vec<u8>),
+    /// Receive the data sent by the host and acknowledge the request.
+    Receive,
+    /// Reject the request.
+    Stall,
+}
+
+/// Door lamp and rumble status set by the host.
+#[derive(Default, Clone, Copy, PartialEq)]
+pub struct Feedback {
+    pub lamp: bool,
+    pub rumble: bool,
+}
+
+/// Constant data of a model, defined once in its module.
+pub struct ModelInfo {
+    /// Short identifier used in settings, matching the product code of the model.
+    pub id: &'static str,
+    /// Human-readable model name.
+    pub name: &'static str,
+    /// USB device descriptor passed to the gadget driver.
+    pub device_descriptor: &'static DeviceDescriptor,
+    /// FunctionFS descriptors written to EP0.
+    pub descriptors: &'static [u8],
+    /// FunctionFS strings written to EP0.
+    pub strings: &'static [u8],
+    /// HID report descriptor, if the model is a HID device.
+    pub hid_report_descriptor: Option<&'static [u8]>,
+    /// Controls held at boot to select this model.
+    pub chord: Chord,
+    /// Feedback played once the model is set up, telling the model family and variant.
+    pub confirmation: Pattern,
+}
+
+/// A controller model that can be emulated through the USB gadget.
+pub trait EmulatedController: Sync {
+    /// Constant data of the model.
+    fn info(&self) -> &'static ModelInfo;
+
+    /// Build the input report for the current controller state.
+    fn encode_report(&self, state: &mut ControllerState) -> Vec<u8>;
+
+    /// Handle a model-specific control request, stalling it by default.
+    fn control_request(&self, _request: &ControlRequest) -> Control {
+        Control::Stall
+    }
+
+    /// Handle the data of a host-to-device control request accepted with `Control::Receive`.
+    fn control_data(&self, _request: &ControlRequest, _data: &[u8], _feedback: &mut Feedback) {}
+
+    /// Whether the model receives data from the host on an OUT endpoint.
+    fn has_out_endpoint(&self) -> bool {
+        false
+    }
+
+    /// Handle data received on the OUT endpoint.
+    fn out_data(&self, _data: &[u8], _feedback: &mut Feedback) {}
+
+    /// Forget what the host set during a previous session of the model.
+    fn reset(&self) {}
+}
+
+/// Controls held at boot to start the RNDIS gadget instead of the remembered or default model.
+const RNDIS_CHORD: Chord = Chord {
+    buttons: ChordButtons::SELECT,
+    power: None,
+    brake: None,
+};
+
+/// Controls held at boot to pick the model in the menu.
+const MENU_CHORD: Chord = Chord {
+    buttons: ChordButtons::START,
+    power: None,
+    brake: None,
+};
+
+const RNDIS: &str = "RNDIS";
+const MENU: &str = "menu";
+
+/// Longest wait for the host to read the last reports when closing.
+const FLUSH_TIMEOUT: Duration = Duration::from_millis(200);
+
+// Standard and HID class control requests
+const GET_DESCRIPTOR: u8 = 0x06;
+const HID_GET_REPORT: u8 = 0x01;
+const HID_GET_IDLE: u8 = 0x02;
+const HID_GET_PROTOCOL: u8 = 0x03;
+const HID_SET_REPORT: u8 = 0x09;
+const HID_SET_IDLE: u8 = 0x0A;
+const HID_SET_PROTOCOL: u8 = 0x0B;
+
+const HID_REPORT_DESCRIPTOR: u16 = 0x22;
+const HID_REPORT_INPUT: u16 = 0x01;
+const HID_REPORT_OUTPUT: u16 = 0x02;
+const HID_REPORT_FEATURE: u16 = 0x03;
+const HID_PROTOCOL_REPORT: u8 = 1;
+
+/// Largest transfer received on the OUT endpoint.
+const OUT_PACKET_SIZE: usize = 64;
+
+/// Wait before reading EP0 or EP2 again after it failed.
+const EVENT_RETRY: Duration = Duration::from_millis(100);
+
+/// Longest wait for an EP0 event before checking whether the session is closing.
+const EVENT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Signal interrupting endpoint transfers that cannot time out, such as EP1 and EP2 ones.
+const INTERRUPT: Signal = Signal::SIGUSR2;
+
+/// Attempts to interrupt a session thread before leaving it behind.
+const STOP_ATTEMPTS: u32 = 50;
+const STOP_RETRY: Duration = Duration::from_millis(20);
+
+/// All supported controllers, in the order of the menu.
+static CONTROLLERS: [&dyn EmulatedController; 13] = [
+    &dgoc44u::Dgoc44u,
+    &zkns001::Zkns001,
+    &slph00051::Slph00051,
+    &tcpp20003::Tcpp20003,
+    &tc5175290::Tc5175290,
+    &tcpp20004::Tcpp20004,
+    &tcpp20009::Tcpp20009,
+    &generic::Generic,
+    &tcpp20011::Tcpp20011,
+    &sotp031201_p4b7::Sotp031201P4B7,
+    &sotp031201_p4b2b7::Sotp031201P4B2B7,
+    &sotp031201_p5b5::Sotp031201P5B5,
+    &sotp031201_p5b7::Sotp031201P5B7,
+];
+
+/// An emulated controller presented to the host through the gadget.
+pub struct Session {
+    controller: &'static dyn EmulatedController,
+    gadget: Arc<dyn Gadget>,
+    state: Mutex<GadgetState>,
+    feedback: Mutex<Feedback>,
+    report: Mutex<Report>,
+    pending: Mutex<Pending>,
+    pending_ready: Condvar,
+    closed: AtomicBool,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// Report handed over to the writer thread.
+#[derive(Default)]
+struct Pending {
+    data: Option<Vec<u8>>,
+    writing: bool,
+}
+
+/// Last input report sent to the host.
+struct Report {
+    data: Vec<u8>,
+    sent: Instant,
+    idle: Option<Duration>,
+}
+
+impl Session {
+    pub fn model(&self) -> ControllerModel {
+        ControllerModel(self.controller)
+    }
+
+    /// Connection state of the gadget with the host.
+    pub fn state(&self) -> GadgetState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Let the model update the feedback, applying it right away if it changed.
+    fn update_feedback(&self, update: impl FnOnce(&mut Feedback)) {
+        let mut feedback = self.feedback.lock().unwrap();
+        let previous = *feedback;
+        update(&mut feedback);
+        if *feedback != previous {
+            debug!(
+                "Host set lamp {} and rumble {}",
+                feedback.lamp, feedback.rumble
+            );
+            set_lamp(feedback.lamp).ok();
+            set_rumble(feedback.rumble).ok();
+        }
+    }
+
+    /// Hand a report over to the writer thread, replacing any report not yet written.
+    fn send_report(&self, data: Vec<u8>) {
+        self.pending.lock().unwrap().data = Some(data);
+        self.pending_ready.notify_all();
+    }
+
+    /// Wait until the writer thread has written every report, at most for `timeout`.
+    fn flush(&self, timeout: Duration) {
+        let pending = self.pending.lock().unwrap();
+        let _ = self
+            .pending_ready
+            .wait_timeout_while(pending, timeout, |p| p.data.is_some() || p.writing)
+            .unwrap();
+    }
+
+    /// Time left until the current report has to be sent again, if the host can receive it.
+    pub fn report_timeout(&self) -> Option<Duration> {
+        if self.state() != GadgetState::Enabled {
+            return None;
         }
-        ControllerModel::TC5175290 => {
-            tc5175290::update_gadget(state);
+        let report = self.report.lock().unwrap();
+        report
+            .idle
+            .map(|idle| idle.saturating_sub(report.sent.elapsed()))
+    }
+}
+
+/// Model selected by the controls held at boot.
+pub fn selected_model(state: &ControllerState) -> Selection {
+    let chords =
+        special_chords().chain(CONTROLLERS.iter().map(|c| (c.info().name, &c.info().chord)));
+    let matches: Vec<&'static str> = chords
+        .filter(|(_, chord)| chord.matches(state))
+        .map(|(name, _)| name)
+        .collect();
+    match matches[..] {
+        [] => Selection::None,
+        [RNDIS] => Selection::Rndis,
+        [MENU] => Selection::Menu,
+        [name] => Selection::Model(ControllerModel(
+            *CONTROLLERS.iter().find(|c| c.info().name == name).unwrap(),
+        )),
+        _ => Selection::Ambiguous(matches),
+    }
+}
+
+/// Chords that select an action instead of a model, with their names.
+fn special_chords() -> impl Iterator<Item = (&'static str, &'static Chord)> {
+    [(RNDIS, &RNDIS_CHORD), (MENU, &MENU_CHORD)].into_iter()
+}
+
+/// Model used when no chord is held, the last selected one if it is remembered or the default.
+pub fn fallback_model() -> Option<ControllerModel> {
+    config::last_model().or(config::get().default_model)
+}
+
+/// Check that every model can be selected without also matching the chord of another one.
+pub fn check_chords() -> Result<()> {
+    let chords: Vec<(&'static str, &'static Chord)> = CONTROLLERS
+        .iter()
+        .map(|c| (c.info().name, &c.info().chord))
+        .chain(special_chords())
+        .collect();
+    for (i, (a, a_chord)) in chords.iter().enumerate() {
+        for (b, b_chord) in &chords[i + 1..] {
+            if a_chord.covers(b_chord) || b_chord.covers(a_chord) {
+                return Err(Error::ChordConflict(a, b));
+            }
         }
-        ControllerModel::TCPP20003 => {
-            tcpp20003::update_gadget(state);
+    }
+    Ok(())
+}
+
+/// All supported models.
+pub fn models() -> impl Iterator<Item = ControllerModel> {
+    CONTROLLERS.iter().map(|&c| ControllerModel(c))
+}
+
+/// Set up the gadget for the model, or start the RNDIS gadget if there is none.
+pub fn set_model(
+    model: Option<ControllerModel>,
+    gadget: &Arc<dyn Gadget>,
+) -> Result<Option<Arc<Session>>> {
+    match model {
+        Some(model) => {
+            let controller = model.0;
+            info!("Selected controller {}.", controller.info().name);
+            let session = Arc::new(Session {
+                controller,
+                gadget: Arc::clone(gadget),
+                state: Mutex::new(GadgetState::Unbound),
+                feedback: Mutex::new(Feedback::default()),
+                report: Mutex::new(Report {
+                    data: Vec::new(),
+                    sent: Instant::now(),
+                    idle: config::get().keep_alive,
+                }),
+                pending: Mutex::new(Pending::default()),
+                pending_ready: Condvar::new(),
+                closed: AtomicBool::new(false),
+                threads: Mutex::new(Vec::new()),
+            });
+            controller.reset();
+            init_gadget(&session)?;
+            config::save_model(model);
+            Ok(Some(session))
         }
-        ControllerModel::TCPP20004 => {
-            tcpp20004::update_gadget(state);
+        None => {
+            info!("No controller selected, starting RNDIS gadget.");
+            Command::new("rndis-gadget.sh").output().ok();
+            Ok(None)
         }
-        ControllerModel::TCPP20009 => {
-            tcpp20009::update_gadget(state);
+    }
+}
+
+pub fn set_state(state: &mut ControllerState, session: &Session) {
+    // Keep the lamp and rumble as set by the host
+    let feedback = *session.feedback.lock().unwrap();
+    state.lamp = feedback.lamp;
+    state.rumble = feedback.rumble;
+
+    let data = session.controller.encode_report(state);
+    let mut report = session.report.lock().unwrap();
+
+    // Only send the report if it changed or the idle period is over, and the host can read it
+    let idle = report
+        .idle
+        .is_some_and(|idle| report.sent.elapsed() >= idle);
+    if data != report.data || idle {
+        if session.state() == GadgetState::Enabled {
+            session.send_report(data.clone());
         }
-        ControllerModel::TCPP20011 => {
-            tcpp20011::update_gadget(state);
+        report.data = data;
+        report.sent = Instant::now();
+    }
+}
+
+pub fn handle_ctrl_transfer(session: &Session, request: &ControlRequest) {
+    trace!(
+        "CTRL REQ ({:?}): bmRequestType={:#04x} bRequest={:#04x} wValue={:#06x} wIndex={:#06x} wLength={}",
+        session.model(),
+        request.request_type,
+        request.request,
+        request.value,
+        request.index,
+        request.length
+    );
+    let controller = session.controller;
+    let hid = controller.info().hid_report_descriptor.is_some();
+    let generic = match (request.request_type, request.request) {
+        (0x81, GET_DESCRIPTOR) if request.value >> 8 == HID_REPORT_DESCRIPTOR => {
+            // Get HID report descriptor
+            Some(match controller.info().hid_report_descriptor {
+                Some(report) => Control::Reply(report.to_vec()),
+                None => Control::Stall,
+            })
         }
-        ControllerModel::SOTP031201P4B7 => {
-            sotp031201_p4b7::update_gadget(state);
+        (0xA1, HID_GET_REPORT) if hid && request.value >> 8 == HID_REPORT_INPUT => {
+            Some(Control::Reply(current_report(session)))
         }
-        ControllerModel::SOTP031201P4B2B7 => {
-            sotp031201_p4b2b7::update_gadget(state);
+        (0xA1, HID_GET_IDLE) if hid => {
+            let idle = session.report.lock().unwrap().idle;
+            let duration = idle.map_or(0, |idle| (idle.as_millis() / 4).min(255) as u8);
+            Some(Control::Reply(vec![duration]))
         }
-        ControllerModel::SOTP031201P5B5 => {
-            sotp031201_p5b5::update_gadget(state);
+        (0x21, HID_SET_IDLE) if hid => {
+            // Duration in units of 4 ms where 0 means only on change
+            session.report.lock().unwrap().idle = match request.value >> 8 {
+                0 => None,
+                duration => Some(Duration::from_millis(duration as u64 * 4)),
+            };
+            Some(Control::Receive)
         }
-        ControllerModel::SOTP031201P5B7 => {
-            sotp031201_p5b7::update_gadget(state);
+        // Only the report protocol is supported, as none of the models are boot devices
+        (0xA1, HID_GET_PROTOCOL) if hid => Some(Control::Reply(vec![HID_PROTOCOL_REPORT])),
+        (0x21, HID_SET_PROTOCOL) if hid => Some(Control::Receive),
+        _ => None,
+    };
+    // Only requests accepted by the model carry data for it
+    let from_model = generic.is_none();
+    let response = generic.unwrap_or_else(|| controller.control_request(request));
+
+    let result = match response {
+        Control::Reply(mut data) => {
+            data.truncate(request.length as usize);
+            session.gadget.write_ep0(&data)
         }
-        ControllerModel::ZKNS001 => {
-            zkns001::update_gadget(state);
+        Control::Receive => session
+            .gadget
+            .read_ep0(request.length as usize)
+            .map(|data| {
+                if from_model {
+                    session.update_feedback(|feedback| {
+                        controller.control_data(request, &data, feedback)
+                    })
+                }
+            }),
+        Control::Stall => {
+            debug!(
+                "Stalling unsupported control request {:#04x}/{:#04x}",
+                request.request_type, request.request
+            );
+            session.gadget.stall_ep0(request)
         }
-        ControllerModel::SLPH00051 => {
-            slph00051::update_gadget(state);
+    };
+    if let Err(e) = result {
+        warn!("{}", e);
+    }
+}
+
+/// Last input report sent to the host, or the report of released controls if there is none.
+fn current_report(session: &Session) -> Vec<u8> {
+    let data = session.report.lock().unwrap().data.clone();
+    if !data.is_empty() {
+        return data;
+    }
+    let mut state = ControllerState::default();
+    state.set_neutral();
+    session.controller.encode_report(&mut state)
+}
+
+/// Send a report with every control released to the host.
+pub fn release_controls(session: &Session) {
+    let mut state = ControllerState::default();
+    state.set_neutral();
+    set_state(&mut state, session);
+    session.flush(FLUSH_TIMEOUT);
+}
+
+/// Tear down the gadget of the session and stop its threads.
+pub fn close(session: &Session) {
+    // Set under the lock so the EP1 writer cannot miss the wakeup
+    let pending = session.pending.lock().unwrap();
+    session.closed.store(true, Ordering::Relaxed);
+    session.pending_ready.notify_all();
+    drop(pending);
+
+    // Stop the threads first, FunctionFS cannot be unmounted while they hold endpoints
+    for thread in session.threads.lock().unwrap().drain(..) {
+        stop_thread(thread);
+    }
+    session.gadget.close();
+}
+
+/// Wait for a session thread to notice the session closing, interrupting the endpoint
+/// transfer it may be blocked in.
+fn stop_thread(thread: JoinHandle<()>) {
+    let mut attempts = 0;
+    while !thread.is_finished() {
+        if attempts == STOP_ATTEMPTS {
+            warn!("Session thread did not stop, leaving it behind.");
+            return;
         }
-        ControllerModel::GENERIC => {
-            generic::update_gadget(state);
+        pthread_kill(thread.as_pthread_t(), INTERRUPT).ok();
+        attempts += 1;
+        sleep(STOP_RETRY);
+    }
+    thread.join().ok();
+}
+
+/// Make the interrupt signal fail blocking reads and writes instead of restarting them.
+fn install_interrupt() {
+    static INSTALL: Once = Once::new();
+    extern "C" fn interrupted(_: c_int) {}
+    INSTALL.call_once(|| {
+        let action = SigAction::new(
+            SigHandler::Handler(interrupted),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+        // The handler does nothing, so it cannot break the interrupted thread
+        if let Err(e) = unsafe { sigaction(INTERRUPT, &action) } {
+            warn!("Could not install interrupt signal handler: {}", e);
         }
+    });
+}
+
+/// Follow the connection state of the gadget and answer control transfers.
+fn handle_event(session: &Session, event: &Event) {
+    if let Event::Setup(request) = event {
+        handle_ctrl_transfer(session, request);
+        return;
+    }
+
+    debug!("Gadget event {:?}", event);
+    let mut state = session.state.lock().unwrap();
+    let previous = *state;
+    *state = previous.next(event);
+    let current = *state;
+    drop(state);
+    if current == previous {
+        return;
+    }
+    info!("Gadget {}, was {}.", current, previous);
+    if current == GadgetState::Enabled {
+        // Endpoints are ready, send the current report
+        session.send_report(current_report(session));
     }
 }
 
-pub fn handle_ctrl_transfer(model: ControllerModel, data: &[u8]) {
-    println!("ddgo-pnp-controller: CTRL REQ: {:?}", data);
-    if data[1] == 6 && data[3] == 34 {
-        // Get HID report descriptor
-        let report: Option<&[u8]>;
-        match model {
-            ControllerModel::DGOC44U => {
-                report = Some(&dgoc44u::HID_REPORT_DESCRIPTOR);
-            }
-            ControllerModel::ZKNS001 => {
-                report = Some(&zkns001::HID_REPORT_DESCRIPTOR);
-            }
-            ControllerModel::SLPH00051 => {
-                report = Some(&slph00051::HID_REPORT_DESCRIPTOR);
-            }
-            ControllerModel::TC5175290 => {
-                report = Some(&tc5175290::HID_REPORT_DESCRIPTOR);
-            }
-            ControllerModel::TCPP20003 => {
-                report = Some(&tcpp20003::HID_REPORT_DESCRIPTOR);
+fn init_gadget(session: &Arc<Session>) -> Result<()> {
+    let controller = session.controller;
+    session.gadget.init(
+        controller.info().device_descriptor,
+        controller.info().descriptors,
+        controller.info().strings,
+    )?;
+
+    install_interrupt();
+    let ep0_session = Arc::clone(session);
+    let ep0_thread = thread::spawn(move || {
+        let mut failing = false;
+        while !ep0_session.closed.load(Ordering::Relaxed) {
+            match ep0_session.gadget.read_event(EVENT_TIMEOUT) {
+                Ok(Some(event)) => {
+                    failing = false;
+                    handle_event(&ep0_session, &event);
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    // Only report the first failure until events can be read again
+                    if !failing && !ep0_session.closed.load(Ordering::Relaxed) {
+                        debug!("Could not read event from EP0: {}", e);
+                    }
+                    failing = true;
+                    sleep(EVENT_RETRY);
+                }
             }
-            ControllerModel::TCPP20004 => {
-                report = Some(&tcpp20004::HID_REPORT_DESCRIPTOR);
+        }
+    });
+
+    // Write reports in their own thread so a slow host never blocks the input loop
+    let ep1_session = Arc::clone(session);
+    let ep1_thread = thread::spawn(move || {
+        loop {
+            let mut pending = ep1_session.pending.lock().unwrap();
+            while pending.data.is_none() && !ep1_session.closed.load(Ordering::Relaxed) {
+                pending = ep1_session.pending_ready.wait(pending).unwrap();
             }
-            ControllerModel::GENERIC => {
-                report = Some(&generic::HID_REPORT_DESCRIPTOR);
+            if ep1_session.closed.load(Ordering::Relaxed) {
+                return;
             }
-            _ => {
-                report = None;
+            let data = pending.data.take().unwrap();
+            pending.writing = true;
+            drop(pending);
+            // Reports are resent once the host enables the function
+            if ep1_session.state() == GadgetState::Enabled {
+                match ep1_session.gadget.write_ep1(&data) {
+                    Err(Error::EndpointWrite(_, e)) if e.kind() == ErrorKind::NotConnected => (),
+                    Err(_) if ep1_session.closed.load(Ordering::Relaxed) => (),
+                    Err(e) => warn!("{}", e),
+                    Ok(()) => (),
+                }
             }
+            ep1_session.pending.lock().unwrap().writing = false;
+            ep1_session.pending_ready.notify_all();
         }
-        match report {
-            Some(rep) => {
-                if let Ok(mut file) = File::create(ENDPOINT0) {
-                    file.write(rep).ok();
+    });
+    let mut threads = vec![ep0_thread, ep1_thread];
+
+    // Read what the host sends on the OUT endpoint of models that have one
+    if controller.has_out_endpoint() {
+        let ep2_session = Arc::clone(session);
+        threads.push(thread::spawn(move || {
+            let mut buffer = [0; OUT_PACKET_SIZE];
+            let mut failing = false;
+            while !ep2_session.closed.load(Ordering::Relaxed) {
+                match ep2_session.gadget.read_ep2(&mut buffer) {
+                    Ok(length) if length > 0 => {
+                        failing = false;
+                        let data = &buffer[..length];
+                        ep2_session.update_feedback(|feedback| {
+                            ep2_session.controller.out_data(data, feedback)
+                        });
+                    }
+                    result => {
+                        // EP2 is only open while the function is enabled
+                        if let Err(e) = result
+                            && !failing
+                            && !matches!(&e, Error::EndpointRead(_, e) if e.kind() == ErrorKind::NotConnected)
+                            && !ep2_session.closed.load(Ordering::Relaxed)
+                        {
+                            debug!("{}", e);
+                        }
+                        failing = true;
+                        sleep(EVENT_RETRY);
+                    }
                 }
             }
-            None => (),
-        }
+        }));
     }
+    *session.threads.lock().unwrap() = threads;
+    Ok(())
 }
 
-fn init_gadget(
-    model: &ControllerModel,
-    (device, descriptors, strings): (&DeviceDescriptor, &[u8], &[u8]),
-) {
-    // Init g_ffs kernel module
-    Command::new("modprobe")
-        .arg("g_ffs")
-        .arg(String::from("bDeviceClass=") + &device.b_device_class.to_string())
-        .arg(String::from("bDeviceSubClass=") + &device.b_device_sub_class.to_string())
-        .arg(String::from("idVendor=") + &device.id_vendor.to_string())
-        .arg(String::from("idProduct=") + &device.id_product.to_string())
-        .arg(String::from("bcdDevice=") + &device.bcd_device.to_string())
-        .arg(String::from("iManufacturer=") + device.i_manufacturer)
-        .arg(String::from("iProduct=") + device.i_product)
-        .arg(String::from("iSerialNumber=") + device.i_serial_number)
-        .output()
-        .ok();
-    Command::new("mkdir").args(["-p", &FFS_MOUNT]).output().ok();
-    Command::new("mount")
-        .args(["-t", "functionfs", "ffs", &FFS_MOUNT])
-        .output()
-        .ok();
-
-    let controller_model = model.clone();
-
-    thread::spawn(move || {
-        if let Ok(mut ep0) = File::open(&ENDPOINT0) {
-            let mut buffer = [0; 12];
-            loop {
-                if let Ok(_result) = ep0.read(&mut buffer) {
-                    if buffer[8] == 0x4 {
-                        // Control transfer received
-                        handle_ctrl_transfer(controller_model, &buffer[0..8]);
-                    }
-                }
-                // Wait between cycles
-                sleep(Duration::from_millis(10));
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use super::*;
+    use crate::controller::gadget::DirectoryGadget;
+
+    /// Held by tests running a session, as models keep what the host set in statics.
+    static SESSIONS: Mutex<()> = Mutex::new(());
+
+    /// Longest wait for the session threads to handle the host.
+    const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Vendor request stalled by every model, marking the end of a script.
+    const END: [u8; 8] = [0xC0, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00];
+
+    /// Control transfers of a script as seen by the host.
+    pub(crate) struct Transfers {
+        /// Data sent back by the device, one reply after the other.
+        pub(crate) replies: Vec<u8>,
+        /// Setup packets of the stalled requests.
+        pub(crate) stalls: Vec<u8>,
+        /// Lamp and rumble status once the script is done.
+        pub(crate) feedback: Feedback,
+    }
+
+    /// Empty directory for the gadget of a test.
+    fn directory(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ddgo-pnp-{}-{}", name, process::id()));
+        fs::remove_dir_all(&path).ok();
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// Emulate the model on a directory gadget in `path`.
+    fn start(model: ControllerModel, path: &Path) -> Arc<Session> {
+        let gadget: Arc<dyn Gadget> = Arc::new(DirectoryGadget::new(path.to_str().unwrap()));
+        set_model(Some(model), &gadget).unwrap().unwrap()
+    }
+
+    /// Wait until the session threads made the condition true.
+    fn wait_until(what: &str, condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < TEST_TIMEOUT, "{} timed out", what);
+            sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// SETUP event of a control request, followed by the data stage sent by the host.
+    pub(crate) fn setup(
+        request_type: u8,
+        request: u8,
+        value: u16,
+        length: u16,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let request = ControlRequest {
+            request_type,
+            request,
+            value,
+            index: 0,
+            length,
+        };
+        [&Event::Setup(request).to_bytes()[..], data].concat()
+    }
+
+    /// Run the control transfers after the host enabled the model.
+    pub(crate) fn transfer(model: ControllerModel, script: &[Vec<u8>]) -> Transfers {
+        let _sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+        let path = directory(&format!("{}-transfer", model.id()));
+        let events = [
+            Event::Bind.to_bytes().to_vec(),
+            Event::Enable.to_bytes().to_vec(),
+        ]
+        .into_iter()
+        .chain(script.iter().cloned())
+        .chain([setup(0xC0, 0xFF, 0, 1, &[])])
+        .collect::<Vec<_>>()
+        .concat();
+        fs::write(path.join("events"), events).unwrap();
+
+        let session = start(model, &path);
+        let stalls = path.join("stalls");
+        wait_until("script", || {
+            fs::read(&stalls).is_ok_and(|stalls| stalls.ends_with(&END))
+        });
+        let feedback = *session.feedback.lock().unwrap();
+        close(&session);
+
+        let info = model.0.info();
+        let ep0 = fs::read(path.join("ep0")).unwrap();
+        let mut stalls = fs::read(&stalls).unwrap();
+        stalls.truncate(stalls.len() - END.len());
+        fs::remove_dir_all(&path).ok();
+        Transfers {
+            replies: ep0[info.descriptors.len() + info.strings.len()..].to_vec(),
+            stalls,
+            feedback,
+        }
+    }
+
+    /// Emulate the model on a directory gadget enabled by the host, returning the reports
+    /// written on EP1 for released controls and then for the state.
+    fn emulate(model: ControllerModel, state: &mut ControllerState) -> (Vec<u8>, Vec<u8>) {
+        let _sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+        let path = directory(model.id());
+        let session = start(model, &path);
+        wait_until("enable", || session.state() == GadgetState::Enabled);
+        session.flush(FLUSH_TIMEOUT);
+        let neutral = fs::read(path.join("ep1")).unwrap();
+        set_state(state, &session);
+        session.flush(FLUSH_TIMEOUT);
+        close(&session);
+        let mut reports = fs::read(path.join("ep1")).unwrap();
+        fs::remove_dir_all(&path).ok();
+        (neutral.clone(), reports.split_off(neutral.len()))
+    }
+
+    /// Reports of every model for released controls, and for power notch 3 with A, D and UP.
+    const REPORTS: [(&str, &[u8], &[u8]); 13] = [
+        (
+            "DGOC44-U",
+            &[0xB9, 0x81, 0x00, 0x00, 0x00, 0x00],
+            &[0x79, 0x3F, 0x00, 0x1A, 0x00, 0x00],
+        ),
+        (
+            "SLPH-00051",
+            &[0x80, 0x80, 0x30, 0x80, 0x01, 0x00],
+            &[0x80, 0x80, 0x11, 0x00, 0x00, 0x00],
+        ),
+        (
+            "SOTP-031201-P4B7",
+            &[0x01, 0x01, 0x00, 0x00],
+            &[0x01, 0x0C, 0x06, 0x04],
+        ),
+        (
+            "SOTP-031201-P4B2B7",
+            &[0x01, 0x01, 0x00, 0x00],
+            &[0x01, 0x0B, 0x06, 0x04],
+        ),
+        (
+            "SOTP-031201-P5B5",
+            &[0x01, 0x01, 0x00, 0x00],
+            &[0x01, 0x0A, 0x06, 0x04],
+        ),
+        (
+            "SOTP-031201-P5B7",
+            &[0x01, 0x01, 0x00, 0x00],
+            &[0x01, 0x0C, 0x06, 0x04],
+        ),
+        (
+            "TC-5175290",
+            &[0x80, 0x80, 0x00, 0x80, 0x01, 0x00],
+            &[0x80, 0x80, 0x01, 0xF8, 0x00, 0x00],
+        ),
+        (
+            "TCPP-20003",
+            &[0x80, 0x80, 0x00, 0x80, 0x01, 0x00],
+            &[0x80, 0x80, 0x01, 0xF8, 0x00, 0x00],
+        ),
+        (
+            "TCPP-20004",
+            &[0x80, 0x80, 0x00, 0x80, 0x01, 0x00],
+            &[0x80, 0x80, 0x01, 0xF8, 0x00, 0x00],
+        ),
+        (
+            "TCPP-20009",
+            &[0x01, 0xB9, 0x81, 0xFF, 0x08, 0x00],
+            &[0x01, 0x79, 0x3F, 0xFF, 0x00, 0x0A],
+        ),
+        (
+            "TCPP-20011",
+            &[0xFB, 0x12, 0xFF, 0x08, 0x00, 0x00],
+            &[0x1C, 0x90, 0xFF, 0x00, 0x09, 0x00],
+        ),
+        (
+            "ZKNS-001",
+            &[0x40, 0x00, 0x0F, 0x80, 0x00, 0x80, 0x80, 0x00],
+            &[0x09, 0x00, 0x00, 0x80, 0xCE, 0x80, 0x80, 0x00],
+        ),
+        (
+            "GENERIC",
+            &[0xFF, 0x80, 0x00, 0x00, 0x00, 0x00],
+            &[0x80, 0xCC, 0x19, 0x00, 0x00, 0x00],
+        ),
+    ];
+
+    #[test]
+    fn send_reports_of_every_model() {
+        for model in models() {
+            let (_, expected_neutral, expected_report) = REPORTS
+                .iter()
+                .find(|(id, _, _)| *id == model.id())
+                .unwrap_or_else(|| panic!("no reports for {}", model.id()));
+            let mut state = ControllerState {
+                power: 3,
+                brake: 0,
+                button_a: true,
+                button_d: true,
+                button_up: true,
+                ..Default::default()
+            };
+            let (neutral, report) = emulate(model, &mut state);
+            assert_eq!(neutral, *expected_neutral, "{} released", model.id());
+            assert_eq!(report, *expected_report, "{} report", model.id());
+        }
+    }
+
+    #[test]
+    fn answer_hid_requests() {
+        let transfers = transfer(
+            ControllerModel(&zkns001::Zkns001),
+            &[
+                setup(0x81, GET_DESCRIPTOR, 0x2200, 0x0400, &[]),
+                setup(0xA1, HID_GET_REPORT, 0x0100, 64, &[]),
+                setup(0xA1, HID_GET_IDLE, 0, 1, &[]),
+                setup(0x21, HID_SET_IDLE, 0x0300, 0, &[]),
+                setup(0xA1, HID_GET_IDLE, 0, 1, &[]),
+                setup(0x21, HID_SET_PROTOCOL, 0x0001, 0, &[]),
+                setup(0xA1, HID_GET_PROTOCOL, 0, 1, &[]),
+            ],
+        );
+        let descriptor = zkns001::INFO.hid_report_descriptor.unwrap();
+        let (_, neutral, _) = REPORTS
+            .iter()
+            .find(|(id, _, _)| *id == zkns001::INFO.id)
+            .unwrap();
+        assert_eq!(
+            transfers.replies,
+            [descriptor, neutral, &[0], &[3], &[HID_PROTOCOL_REPORT]].concat()
+        );
+        assert!(transfers.stalls.is_empty());
+    }
+
+    #[test]
+    fn truncate_replies_to_requested_length() {
+        let transfers = transfer(
+            ControllerModel(&zkns001::Zkns001),
+            &[setup(0xA1, HID_GET_REPORT, 0x0100, 2, &[])],
+        );
+        assert_eq!(transfers.replies, [0x40, 0x00]);
+    }
+
+    #[test]
+    fn stall_unsupported_requests() {
+        let vendor_in = setup(0xC0, 0x01, 0, 4, &[]);
+        let vendor_out = setup(0x40, 0x01, 0, 2, &[0xAA, 0xBB]);
+        // HID class requests of a model without a HID report descriptor
+        let hid = setup(0xA1, HID_GET_IDLE, 0, 1, &[]);
+        let transfers = transfer(
+            ControllerModel(&sotp031201_p4b7::Sotp031201P4B7),
+            &[vendor_in.clone(), vendor_out.clone(), hid.clone()],
+        );
+        assert!(transfers.replies.is_empty());
+        assert_eq!(
+            transfers.stalls,
+            [&vendor_in[..8], &vendor_out[..8], &hid[..8]].concat()
+        );
+    }
+
+    #[test]
+    fn chord_covers_stricter_chords() {
+        let up = Chord {
+            buttons: ChordButtons::UP,
+            power: None,
+            brake: None,
+        };
+        let up_power = Chord {
+            power: Some(2),
+            ..up
+        };
+        let up_a = Chord {
+            buttons: ChordButtons::UP | ChordButtons::A,
+            ..up
+        };
+        assert!(up.covers(&up));
+        assert!(up.covers(&up_power));
+        assert!(up.covers(&up_a));
+        assert!(!up_power.covers(&up));
+        assert!(!up_a.covers(&up));
+        assert!(!up_power.covers(&Chord {
+            power: Some(3),
+            ..up
+        }));
+        assert!(!up.covers(&Chord {
+            buttons: ChordButtons::DOWN,
+            ..up
+        }));
+    }
+
+    #[test]
+    fn find_every_model_by_id() {
+        for model in models() {
+            let found: ControllerModel = model.id().to_lowercase().parse().unwrap();
+            assert!(
+                std::ptr::addr_eq(found.0, model.0),
+                "{} is not unique",
+                model.id()
+            );
+        }
+    }
+
+    #[test]
+    fn chords_do_not_conflict() {
+        assert!(check_chords().is_ok());
+    }
+
+    #[test]
+    fn select_model_by_chord() {
+        let state = ControllerState {
+            button_down: true,
+            power: 2,
+            ..Default::default()
+        };
+        assert!(matches!(
+            selected_model(&state),
+            Selection::Model(model) if model.id() == tc5175290::INFO.id
+        ));
+    }
+
+    #[test]
+    fn select_nothing_without_chord() {
+        assert!(matches!(
+            selected_model(&ControllerState::default()),
+            Selection::None
+        ));
+    }
+
+    #[test]
+    fn reject_ambiguous_selection() {
+        let state = ControllerState {
+            button_a: true,
+            button_b: true,
+            ..Default::default()
+        };
+        match selected_model(&state) {
+            Selection::Ambiguous(names) => {
+                assert_eq!(names, [generic::INFO.name, tcpp20011::INFO.name])
             }
+            _ => panic!("A+B should be ambiguous"),
         }
-    });
-    if let Ok(mut ep0) = File::create(&ENDPOINT0) {
-        ep0.write_all(descriptors).ok();
-        println!("ddgo-pnp-controller: Descriptors written to EP0");
-        ep0.write_all(strings).ok();
-        println!("ddgo-pnp-controller: Strings written to EP0");
-    }
-
-    // Init Android Gadget for old 3.4 kernel
-    let gadget = Path::new(&ANDROID_GADGET);
-    if gadget.is_dir() {
-        fs::write(
-            gadget.join(Path::new("bDeviceClass")),
-            &device.b_device_class.to_string(),
-        )
-        .ok();
-        fs::write(
-            gadget.join(Path::new("bDeviceSubClass")),
-            &device.b_device_sub_class.to_string(),
-        )
-        .ok();
-        fs::write(
-            gadget.join(Path::new("idVendor")),
-            format!("{:x}", &device.id_vendor),
-        )
-        .ok();
-        fs::write(
-            gadget.join(Path::new("idProduct")),
-            format!("{:x}", &device.id_product),
-        )
-        .ok();
-        fs::write(
-            gadget.join(Path::new("bcdDevice")),
-            format!("{:x}", &device.bcd_device),
-        )
-        .ok();
-        fs::write(
-            gadget.join(Path::new("iManufacturer")),
-            &device.i_manufacturer.to_string(),
-        )
-        .ok();
-        fs::write(
-            gadget.join(Path::new("iProduct")),
-            &device.i_product.to_string(),
-        )
-        .ok();
-        fs::write(
-            gadget.join(Path::new("iSerial")),
-            &device.i_serial_number.to_string(),
-        )
-        .ok();
-        fs::write(gadget.join(Path::new("functions")), "ffs").ok();
-        fs::write(gadget.join(Path::new("f_ffs/aliases")), "ffs").ok();
-        fs::write(gadget.join(Path::new("enable")), "1").ok();
     }
 }
@@ -1,8 +1,7 @@
-use std::fs::File;
-use std::io::Write;
-
-use crate::controller::emulated::{DeviceDescriptor, ENDPOINT1};
-use crate::controller::physical::ControllerState;
+use crate::controller::emulated::{
+    Chord, ChordButtons, DeviceDescriptor, EmulatedController, ModelInfo,
+};
+use crate::controller::physical::{ControllerState, Pattern};
 use bitflags::bitflags;
 
 pub const DESCRIPTORS: [u8; 66] = [
@@ -27,6 +26,24 @@ pub const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
     i_serial_number: "DGOC-44U_PNP",
 };
 
+pub const INFO: ModelInfo = ModelInfo {
+    id: "DGOC44-U",
+    name: "DGOC44-U",
+    device_descriptor: &DEVICE_DESCRIPTOR,
+    descriptors: &DESCRIPTORS,
+    strings: &STRINGS,
+    hid_report_descriptor: Some(&HID_REPORT_DESCRIPTOR),
+    chord: Chord {
+        buttons: ChordButtons::RIGHT,
+        power: None,
+        brake: None,
+    },
+    confirmation: Pattern {
+        pulses: 2,
+        blinks: 0,
+    },
+};
+
 pub const HID_REPORT_DESCRIPTOR: [u8; 63] = [
     0x05, 0x01, // Usage Page (Generic Desktop Ctrls)
     0x09, 0x04, // Usage (Joystick)
@@ -81,47 +98,52 @@ bitflags! {
     }
 }
 
-pub fn update_gadget(state: &mut ControllerState) {
-    // Calculate data for handles
-    let power = POWER_NOTCHES[state.power as usize];
-    let brake = BRAKE_NOTCHES[state.brake as usize];
+pub struct Dgoc44u;
 
-    // Calculate data for buttons
-    let mut buttons = Buttons::NONE;
-    if state.button_a {
-        buttons.insert(Buttons::A)
-    }
-    if state.button_b {
-        buttons.insert(Buttons::B)
-    }
-    if state.button_c {
-        buttons.insert(Buttons::C)
-    }
-    if state.button_d {
-        buttons.insert(Buttons::D)
-    }
-    if state.button_select {
-        buttons.insert(Buttons::SELECT)
-    }
-    if state.button_start {
-        buttons.insert(Buttons::START)
-    }
-    if state.button_up {
-        buttons.insert(Buttons::UP)
-    }
-    if state.button_down {
-        buttons.insert(Buttons::DOWN)
-    }
-    if state.button_left {
-        buttons.insert(Buttons::LEFT)
-    }
-    if state.button_right {
-        buttons.insert(Buttons::RIGHT)
+impl EmulatedController for Dgoc44u {
+    fn info(&self) -> &'static ModelInfo {
+        &INFO
     }
 
-    // Assemble data and send it to gadget
-    let data = [brake, power, 0, buttons.bits, 0, 0];
-    if let Ok(mut file) = File::create(ENDPOINT1) {
-        file.write(&data).ok();
+    fn encode_report(&self, state: &mut ControllerState) -> Vec<u8> {
+        // Calculate data for handles
+        let power = POWER_NOTCHES[state.power as usize];
+        let brake = BRAKE_NOTCHES[state.brake as usize];
+
+        // Calculate data for buttons
+        let mut buttons = Buttons::NONE;
+        if state.button_a {
+            buttons.insert(Buttons::A)
+        }
+        if state.button_b {
+            buttons.insert(Buttons::B)
+        }
+        if state.button_c {
+            buttons.insert(Buttons::C)
+        }
+        if state.button_d {
+            buttons.insert(Buttons::D)
+        }
+        if state.button_select {
+            buttons.insert(Buttons::SELECT)
+        }
+        if state.button_start {
+            buttons.insert(Buttons::START)
+        }
+        if state.button_up {
+            buttons.insert(Buttons::UP)
+        }
+        if state.button_down {
+            buttons.insert(Buttons::DOWN)
+        }
+        if state.button_left {
+            buttons.insert(Buttons::LEFT)
+        }
+        if state.button_right {
+            buttons.insert(Buttons::RIGHT)
+        }
+
+        // Assemble the report
+        vec![brake, power, 0, buttons.bits, 0, 0]
     }
 }
@@ -1,8 +1,8 @@
-use crate::controller::emulated::{DeviceDescriptor, ENDPOINT1};
-use crate::controller::physical::ControllerState;
+use crate::controller::emulated::{
+    Chord, ChordButtons, DeviceDescriptor, EmulatedController, ModelInfo,
+};
+use crate::controller::physical::{ControllerState, Pattern};
 use bitflags::bitflags;
-use std::fs::File;
-use std::io::Write;
 
 pub const DESCRIPTORS: [u8; 66] = [
     0x01, 0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
@@ -28,6 +28,24 @@ pub const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
     i_serial_number: "GENERIC-TRAIN-CONTROLLER",
 };
 
+pub const INFO: ModelInfo = ModelInfo {
+    id: "GENERIC",
+    name: "Generic Train Controller",
+    device_descriptor: &DEVICE_DESCRIPTOR,
+    descriptors: &DESCRIPTORS,
+    strings: &STRINGS,
+    hid_report_descriptor: Some(&HID_REPORT_DESCRIPTOR),
+    chord: Chord {
+        buttons: ChordButtons::A,
+        power: None,
+        brake: None,
+    },
+    confirmation: Pattern {
+        pulses: 7,
+        blinks: 0,
+    },
+};
+
 const POWER_NOTCHES: [u8; 6] = [0x80, 0x94, 0xAC, 0xCC, 0xE4, 0xFF];
 const BRAKE_NOTCHES: [u8; 10] = [0x80, 0x91, 0x9F, 0xAD, 0xBB, 0xC9, 0xD7, 0xE5, 0xF3, 0xFF];
 
@@ -76,55 +94,60 @@ bitflags! {
     }
 }
 
-pub fn update_gadget(state: &mut ControllerState) {
-    // Calculate data for handles
-    let power = POWER_NOTCHES[state.power as usize];
-    let brake = BRAKE_NOTCHES[state.brake as usize];
-
-    // Calculate data for buttons
-    let mut buttons = Buttons::NONE;
+pub struct Generic;
 
-    if state.button_a {
-        buttons.insert(Buttons::A)
-    }
-    if state.button_b {
-        buttons.insert(Buttons::B)
-    }
-    if state.button_c {
-        buttons.insert(Buttons::C)
-    }
-    if state.button_d {
-        buttons.insert(Buttons::D)
-    }
-    if state.button_up {
-        buttons.insert(Buttons::UP)
-    }
-    if state.button_down {
-        buttons.insert(Buttons::DOWN)
-    }
-    if state.button_left {
-        buttons.insert(Buttons::LEFT)
-    }
-    if state.button_right {
-        buttons.insert(Buttons::RIGHT)
-    }
-    if state.button_select {
-        buttons.insert(Buttons::SELECT)
-    }
-    if state.button_start {
-        buttons.insert(Buttons::START)
+impl EmulatedController for Generic {
+    fn info(&self) -> &'static ModelInfo {
+        &INFO
     }
 
-    // Assemble data and send it to gadget
-    let data = [
-        brake,
-        power,
-        buttons.bits.to_le_bytes()[0],
-        buttons.bits.to_le_bytes()[1],
-        buttons.bits.to_le_bytes()[2],
-        buttons.bits.to_le_bytes()[3],
-    ];
-    if let Ok(mut file) = File::create(ENDPOINT1) {
-        file.write(&data).ok();
+    fn encode_report(&self, state: &mut ControllerState) -> Vec<u8> {
+        // Calculate data for handles
+        let power = POWER_NOTCHES[state.power as usize];
+        let brake = BRAKE_NOTCHES[state.brake as usize];
+
+        // Calculate data for buttons
+        let mut buttons = Buttons::NONE;
+
+        if state.button_a {
+            buttons.insert(Buttons::A)
+        }
+        if state.button_b {
+            buttons.insert(Buttons::B)
+        }
+        if state.button_c {
+            buttons.insert(Buttons::C)
+        }
+        if state.button_d {
+            buttons.insert(Buttons::D)
+        }
+        if state.button_up {
+            buttons.insert(Buttons::UP)
+        }
+        if state.button_down {
+            buttons.insert(Buttons::DOWN)
+        }
+        if state.button_left {
+            buttons.insert(Buttons::LEFT)
+        }
+        if state.button_right {
+            buttons.insert(Buttons::RIGHT)
+        }
+        if state.button_select {
+            buttons.insert(Buttons::SELECT)
+        }
+        if state.button_start {
+            buttons.insert(Buttons::START)
+        }
+
+        // Assemble the report
+        vec![
+            brake,
+            power,
+            buttons.bits.to_le_bytes()[0],
+            buttons.bits.to_le_bytes()[1],
+            buttons.bits.to_le_bytes()[2],
+            buttons.bits.to_le_bytes()[3],
+        ]
     }
 }
@@ -1,8 +1,8 @@
-use crate::controller::emulated::{DeviceDescriptor, ENDPOINT1};
-use crate::controller::physical::ControllerState;
+use crate::controller::emulated::{
+    Chord, ChordButtons, DeviceDescriptor, EmulatedController, ModelInfo,
+};
+use crate::controller::physical::{ControllerState, Pattern};
 use bitflags::bitflags;
-use std::fs::File;
-use std::io::Write;
 
 pub const DESCRIPTORS: [u8; 66] = [
     0x01, 0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
@@ -28,6 +28,24 @@ pub const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
     i_serial_number: "SLPH-00051",
 };
 
+pub const INFO: ModelInfo = ModelInfo {
+    id: "SLPH-00051",
+    name: "SLPH-00051",
+    device_descriptor: &DEVICE_DESCRIPTOR,
+    descriptors: &DESCRIPTORS,
+    strings: &STRINGS,
+    hid_report_descriptor: Some(&HID_REPORT_DESCRIPTOR),
+    chord: Chord {
+        buttons: ChordButtons::DOWN,
+        power: Some(0),
+        brake: None,
+    },
+    confirmation: Pattern {
+        pulses: 3,
+        blinks: 1,
+    },
+};
+
 pub const HID_REPORT_DESCRIPTOR: [u8; 51] = [
     0x05, 0x01, // Usage Page (Generic Desktop Ctrls)
     0x09, 0x04, // Usage (Joystick)
@@ -80,106 +98,111 @@ bitflags! {
     }
 }
 
-pub fn update_gadget(state: &mut ControllerState) {
-    let mut buttons = Buttons::NONE;
+pub struct Slph00051;
+
+impl EmulatedController for Slph00051 {
+    fn info(&self) -> &'static ModelInfo {
+        &INFO
+    }
+
+    fn encode_report(&self, state: &mut ControllerState) -> Vec<u8> {
+        let mut buttons = Buttons::NONE;
 
-    // If D is pressed, D-pad mode is active
-    if !state.button_d {
-        // Calculate data for handles
-        buttons.insert(Buttons::UP | Buttons::DOWN);
-        match state.power {
-            0 => {
-                buttons.insert(Buttons::POWER2 | Buttons::POWER3);
+        // If D is pressed, D-pad mode is active
+        if !state.button_d {
+            // Calculate data for handles
+            buttons.insert(Buttons::UP | Buttons::DOWN);
+            match state.power {
+                0 => {
+                    buttons.insert(Buttons::POWER2 | Buttons::POWER3);
+                }
+                1 => {
+                    buttons.insert(Buttons::POWER1 | Buttons::POWER3);
+                }
+                2 => {
+                    buttons.insert(Buttons::POWER3);
+                }
+                3 => {
+                    buttons.insert(Buttons::POWER1 | Buttons::POWER2);
+                }
+                4 => {
+                    buttons.insert(Buttons::POWER2);
+                }
+                _ => {
+                    buttons.insert(Buttons::POWER1);
+                }
             }
-            1 => {
-                buttons.insert(Buttons::POWER1 | Buttons::POWER3);
+            match state.brake {
+                0 => {
+                    buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE3 | Buttons::BRAKE4);
+                }
+                1 => {
+                    buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE3 | Buttons::BRAKE4);
+                }
+                2 => {
+                    buttons.insert(Buttons::BRAKE3 | Buttons::BRAKE4);
+                }
+                3 => {
+                    buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE2 | Buttons::BRAKE4);
+                }
+                4 => {
+                    buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE4);
+                }
+                5 => {
+                    buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE4);
+                }
+                6 => {
+                    buttons.insert(Buttons::BRAKE4);
+                }
+                7 => {
+                    buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE2 | Buttons::BRAKE3);
+                }
+                8 => {
+                    buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE3);
+                }
+                _ => (),
             }
-            2 => {
-                buttons.insert(Buttons::POWER3);
+        } else {
+            // D-pad mode
+            if state.button_up {
+                buttons.insert(Buttons::UP)
             }
-            3 => {
-                buttons.insert(Buttons::POWER1 | Buttons::POWER2);
+            if state.button_down {
+                buttons.insert(Buttons::DOWN)
             }
-            4 => {
-                buttons.insert(Buttons::POWER2);
+            if state.button_left {
+                buttons.insert(Buttons::POWER2)
             }
-            _ => {
-                buttons.insert(Buttons::POWER1);
+            if state.button_right {
+                buttons.insert(Buttons::POWER3)
             }
         }
-        match state.brake {
-            0 => {
-                buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE3 | Buttons::BRAKE4);
-            }
-            1 => {
-                buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE3 | Buttons::BRAKE4);
-            }
-            2 => {
-                buttons.insert(Buttons::BRAKE3 | Buttons::BRAKE4);
-            }
-            3 => {
-                buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE2 | Buttons::BRAKE4);
-            }
-            4 => {
-                buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE4);
-            }
-            5 => {
-                buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE4);
-            }
-            6 => {
-                buttons.insert(Buttons::BRAKE4);
-            }
-            7 => {
-                buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE2 | Buttons::BRAKE3);
-            }
-            8 => {
-                buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE3);
-            }
-            _ => (),
+
+        // Calculate data for buttons
+        if state.button_a {
+            buttons.insert(Buttons::A)
         }
-    } else {
-        // D-pad mode
-        if state.button_up {
-            buttons.insert(Buttons::UP)
+        if state.button_b {
+            buttons.insert(Buttons::B)
         }
-        if state.button_down {
-            buttons.insert(Buttons::DOWN)
+        if state.button_c {
+            buttons.insert(Buttons::C)
         }
-        if state.button_left {
-            buttons.insert(Buttons::POWER2)
+        if state.button_start {
+            buttons.insert(Buttons::START)
         }
-        if state.button_right {
-            buttons.insert(Buttons::POWER3)
+        if state.button_select {
+            buttons.insert(Buttons::SELECT)
         }
-    }
-
-    // Calculate data for buttons
-    if state.button_a {
-        buttons.insert(Buttons::A)
-    }
-    if state.button_b {
-        buttons.insert(Buttons::B)
-    }
-    if state.button_c {
-        buttons.insert(Buttons::C)
-    }
-    if state.button_start {
-        buttons.insert(Buttons::START)
-    }
-    if state.button_select {
-        buttons.insert(Buttons::SELECT)
-    }
 
-    // Assemble data and send it to gadget
-    let data = [
-        0x80,
-        0x80,
-        buttons.bits.to_le_bytes()[0],
-        buttons.bits.to_le_bytes()[1],
-        buttons.bits.to_le_bytes()[2],
-        buttons.bits.to_le_bytes()[3],
-    ];
-    if let Ok(mut file) = File::create(ENDPOINT1) {
-        file.write(&data).ok();
+        // Assemble the report
+        vec![
+            0x80,
+            0x80,
+            buttons.bits.to_le_bytes()[0],
+            buttons.bits.to_le_bytes()[1],
+            buttons.bits.to_le_bytes()[2],
+            buttons.bits.to_le_bytes()[3],
+        ]
     }
-}
\ No newline at end of file
+}
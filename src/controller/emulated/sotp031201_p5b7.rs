@@ -1,8 +1,7 @@
-use std::fs::File;
-use std::io::Write;
-
-use crate::controller::emulated::{DeviceDescriptor, ENDPOINT1};
-use crate::controller::physical::ControllerState;
+use crate::controller::emulated::{
+    Chord, ChordButtons, DeviceDescriptor, EmulatedController, ModelInfo,
+};
+use crate::controller::physical::{ControllerState, Pattern};
 use bitflags::bitflags;
 
 pub const DESCRIPTORS: [u8; 48] = [
@@ -25,6 +24,24 @@ pub const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
     i_serial_number: "SOTP-031201",
 };
 
+pub const INFO: ModelInfo = ModelInfo {
+    id: "SOTP-031201-P5B7",
+    name: "SOTP-031201 (P5/B7 mode)",
+    device_descriptor: &DEVICE_DESCRIPTOR,
+    descriptors: &DESCRIPTORS,
+    strings: &STRINGS,
+    hid_report_descriptor: None,
+    chord: Chord {
+        buttons: ChordButtons::C,
+        power: Some(3),
+        brake: None,
+    },
+    confirmation: Pattern {
+        pulses: 6,
+        blinks: 4,
+    },
+};
+
 const POWER_NOTCHES: [u8; 6] = [0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E];
 const BRAKE_NOTCHES: [u8; 10] = [0x09, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x02, 0x01];
 
@@ -49,70 +66,75 @@ bitflags! {
     }
 }
 
-pub fn update_gadget(state: &mut ControllerState) {
-    // Calculate data for handles
-    let mut handle = POWER_NOTCHES[state.power as usize];
-    if state.brake > 0 {
-        handle = BRAKE_NOTCHES[state.brake as usize];
-    }
+pub struct Sotp031201P5B7;
 
-    // Calculate data for buttons
-    let mut buttons1 = Buttons1::NONE;
-    let mut buttons2 = Buttons2::NONE;
-    if !state.button_select_hold && state.button_select && state.button_a {
-        buttons1.insert(Buttons1::A2);
-        state.combo = true;
-    }
-    if !state.button_select_hold && state.button_select && state.button_d {
-        buttons1.insert(Buttons1::S);
-        state.combo = true;
-    }
-    if !state.button_select_hold && state.button_select && state.button_up {
-        state.reverser = 0x80;
-        state.combo = true;
-    }
-    if !state.button_select_hold && state.button_select && state.button_left {
-        state.reverser = 0x00;
-        state.combo = true;
-    }
-    if !state.button_select_hold && state.button_select && state.button_down {
-        state.reverser = 0x40;
-        state.combo = true;
-    }
-    if !state.combo && state.button_a {
-        buttons1.insert(Buttons1::A)
-    }
-    if state.button_b {
-        buttons1.insert(Buttons1::B)
-    }
-    if state.button_c {
-        buttons1.insert(Buttons1::C)
-    }
-    if !state.combo && state.button_d {
-        buttons1.insert(Buttons1::D)
-    }
-    if !state.combo && state.button_up {
-        buttons2.insert(Buttons2::UP)
-    }
-    if !state.combo && state.button_down {
-        buttons2.insert(Buttons2::DOWN)
-    }
-    if !state.combo && state.button_left {
-        buttons2.insert(Buttons2::LEFT)
-    }
-    if state.button_right {
-        buttons2.insert(Buttons2::RIGHT)
-    }
-    if state.button_start {
-        buttons2.insert(Buttons2::START)
-    }
-    if !state.combo && state.button_select_hold {
-        buttons2.insert(Buttons2::SELECT)
+impl EmulatedController for Sotp031201P5B7 {
+    fn info(&self) -> &'static ModelInfo {
+        &INFO
     }
 
-    // Assemble data and send it to endpoint
-    let data = [0x1, state.reverser + handle, buttons1.bits, buttons2.bits];
-    if let Ok(mut file) = File::create(ENDPOINT1) {
-        file.write(&data).ok();
+    fn encode_report(&self, state: &mut ControllerState) -> Vec<u8> {
+        // Calculate data for handles
+        let mut handle = POWER_NOTCHES[state.power as usize];
+        if state.brake > 0 {
+            handle = BRAKE_NOTCHES[state.brake as usize];
+        }
+
+        // Calculate data for buttons
+        let mut buttons1 = Buttons1::NONE;
+        let mut buttons2 = Buttons2::NONE;
+        if !state.button_select_hold && state.button_select && state.button_a {
+            buttons1.insert(Buttons1::A2);
+            state.combo = true;
+        }
+        if !state.button_select_hold && state.button_select && state.button_d {
+            buttons1.insert(Buttons1::S);
+            state.combo = true;
+        }
+        if !state.button_select_hold && state.button_select && state.button_up {
+            state.reverser = 0x80;
+            state.combo = true;
+        }
+        if !state.button_select_hold && state.button_select && state.button_left {
+            state.reverser = 0x00;
+            state.combo = true;
+        }
+        if !state.button_select_hold && state.button_select && state.button_down {
+            state.reverser = 0x40;
+            state.combo = true;
+        }
+        if !state.combo && state.button_a {
+            buttons1.insert(Buttons1::A)
+        }
+        if state.button_b {
+            buttons1.insert(Buttons1::B)
+        }
+        if state.button_c {
+            buttons1.insert(Buttons1::C)
+        }
+        if !state.combo && state.button_d {
+            buttons1.insert(Buttons1::D)
+        }
+        if !state.combo && state.button_up {
+            buttons2.insert(Buttons2::UP)
+        }
+        if !state.combo && state.button_down {
+            buttons2.insert(Buttons2::DOWN)
+        }
+        if !state.combo && state.button_left {
+            buttons2.insert(Buttons2::LEFT)
+        }
+        if state.button_right {
+            buttons2.insert(Buttons2::RIGHT)
+        }
+        if state.button_start {
+            buttons2.insert(Buttons2::START)
+        }
+        if !state.combo && state.button_select_hold {
+            buttons2.insert(Buttons2::SELECT)
+        }
+
+        // Assemble the report
+        vec![0x1, state.reverser + handle, buttons1.bits, buttons2.bits]
     }
 }
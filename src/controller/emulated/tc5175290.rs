@@ -1,8 +1,8 @@
-use crate::controller::emulated::{DeviceDescriptor, ENDPOINT1};
-use crate::controller::physical::ControllerState;
+use crate::controller::emulated::{
+    Chord, ChordButtons, DeviceDescriptor, EmulatedController, ModelInfo,
+};
+use crate::controller::physical::{ControllerState, Pattern};
 use bitflags::bitflags;
-use std::fs::File;
-use std::io::Write;
 
 pub const DESCRIPTORS: [u8; 66] = [
     0x01, 0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
@@ -28,6 +28,24 @@ pub const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
     i_serial_number: "TC-5175290",
 };
 
+pub const INFO: ModelInfo = ModelInfo {
+    id: "TC-5175290",
+    name: "TC-5175290",
+    device_descriptor: &DEVICE_DESCRIPTOR,
+    descriptors: &DESCRIPTORS,
+    strings: &STRINGS,
+    hid_report_descriptor: Some(&HID_REPORT_DESCRIPTOR),
+    chord: Chord {
+        buttons: ChordButtons::DOWN,
+        power: Some(2),
+        brake: None,
+    },
+    confirmation: Pattern {
+        pulses: 3,
+        blinks: 3,
+    },
+};
+
 pub const HID_REPORT_DESCRIPTOR: [u8; 51] = [
     0x05, 0x01, // Usage Page (Generic Desktop Ctrls)
     0x09, 0x04, // Usage (Joystick)
@@ -80,88 +98,93 @@ bitflags! {
     }
 }
 
-pub fn update_gadget(state: &mut ControllerState) {
-    let mut buttons = Buttons::NONE;
+pub struct Tc5175290;
 
-    // Calculate data for handles
-    match state.power {
-        0 => {
-            buttons.insert(Buttons::POWER2 | Buttons::POWER3);
-        }
-        1 => {
-            buttons.insert(Buttons::POWER1 | Buttons::POWER3);
-        }
-        2 => {
-            buttons.insert(Buttons::POWER3);
-        }
-        3 => {
-            buttons.insert(Buttons::POWER1 | Buttons::POWER2);
-        }
-        4 => {
-            buttons.insert(Buttons::POWER2);
-        }
-        _ => {
-            buttons.insert(Buttons::POWER1);
-        }
+impl EmulatedController for Tc5175290 {
+    fn info(&self) -> &'static ModelInfo {
+        &INFO
     }
-    match state.brake {
-        0 => {
-            buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE3 | Buttons::BRAKE4);
-        }
-        1 => {
-            buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE3 | Buttons::BRAKE4);
-        }
-        2 => {
-            buttons.insert(Buttons::BRAKE3 | Buttons::BRAKE4);
+
+    fn encode_report(&self, state: &mut ControllerState) -> Vec<u8> {
+        let mut buttons = Buttons::NONE;
+
+        // Calculate data for handles
+        match state.power {
+            0 => {
+                buttons.insert(Buttons::POWER2 | Buttons::POWER3);
+            }
+            1 => {
+                buttons.insert(Buttons::POWER1 | Buttons::POWER3);
+            }
+            2 => {
+                buttons.insert(Buttons::POWER3);
+            }
+            3 => {
+                buttons.insert(Buttons::POWER1 | Buttons::POWER2);
+            }
+            4 => {
+                buttons.insert(Buttons::POWER2);
+            }
+            _ => {
+                buttons.insert(Buttons::POWER1);
+            }
         }
-        3 => {
-            buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE2 | Buttons::BRAKE4);
+        match state.brake {
+            0 => {
+                buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE3 | Buttons::BRAKE4);
+            }
+            1 => {
+                buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE3 | Buttons::BRAKE4);
+            }
+            2 => {
+                buttons.insert(Buttons::BRAKE3 | Buttons::BRAKE4);
+            }
+            3 => {
+                buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE2 | Buttons::BRAKE4);
+            }
+            4 => {
+                buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE4);
+            }
+            5 => {
+                buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE4);
+            }
+            6 => {
+                buttons.insert(Buttons::BRAKE4);
+            }
+            7 => {
+                buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE2 | Buttons::BRAKE3);
+            }
+            8 => {
+                buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE3);
+            }
+            _ => (),
         }
-        4 => {
-            buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE4);
+
+        // Calculate data for buttons
+        if state.button_a {
+            buttons.insert(Buttons::A)
         }
-        5 => {
-            buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE4);
+        if state.button_b {
+            buttons.insert(Buttons::B)
         }
-        6 => {
-            buttons.insert(Buttons::BRAKE4);
+        if state.button_c {
+            buttons.insert(Buttons::C)
         }
-        7 => {
-            buttons.insert(Buttons::BRAKE1 | Buttons::BRAKE2 | Buttons::BRAKE3);
+        if state.button_start {
+            buttons.insert(Buttons::START)
         }
-        8 => {
-            buttons.insert(Buttons::BRAKE2 | Buttons::BRAKE3);
+        if state.button_select {
+            buttons.insert(Buttons::SELECT)
         }
-        _ => (),
-    }
 
-    // Calculate data for buttons
-    if state.button_a {
-        buttons.insert(Buttons::A)
-    }
-    if state.button_b {
-        buttons.insert(Buttons::B)
-    }
-    if state.button_c {
-        buttons.insert(Buttons::C)
+        // Assemble the report
+        vec![
+            0x80,
+            0x80,
+            buttons.bits.to_le_bytes()[0],
+            buttons.bits.to_le_bytes()[1],
+            buttons.bits.to_le_bytes()[2],
+            buttons.bits.to_le_bytes()[3],
+        ]
     }
-    if state.button_start {
-        buttons.insert(Buttons::START)
-    }
-    if state.button_select {
-        buttons.insert(Buttons::SELECT)
-    }
-
-    // Assemble data and send it to gadget
-    let data = [
-        0x80,
-        0x80,
-        buttons.bits.to_le_bytes()[0],
-        buttons.bits.to_le_bytes()[1],
-        buttons.bits.to_le_bytes()[2],
-        buttons.bits.to_le_bytes()[3],
-    ];
-    if let Ok(mut file) = File::create(ENDPOINT1) {
-        file.write(&data).ok();
-    }
-}
\ No newline at end of file
+}
@@ -1,8 +1,8 @@
-use std::fs::File;
-use std::io::Write;
-
-use crate::controller::emulated::{DeviceDescriptor, ENDPOINT1};
-use crate::controller::physical::ControllerState;
+use crate::controller::emulated::{
+    Chord, ChordButtons, Control, DeviceDescriptor, EmulatedController, Feedback, ModelInfo,
+};
+use crate::controller::gadget::ControlRequest;
+use crate::controller::physical::{ControllerState, Pattern};
 use bitflags::bitflags;
 
 pub const DESCRIPTORS: [u8; 48] = [
@@ -25,6 +25,28 @@ pub const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
     i_serial_number: "TCPP20010",
 };
 
+pub const INFO: ModelInfo = ModelInfo {
+    id: "TCPP-20009",
+    name: "TCPP-20009",
+    device_descriptor: &DEVICE_DESCRIPTOR,
+    descriptors: &DESCRIPTORS,
+    strings: &STRINGS,
+    hid_report_descriptor: None,
+    chord: Chord {
+        buttons: ChordButtons::D,
+        power: None,
+        brake: None,
+    },
+    confirmation: Pattern {
+        pulses: 4,
+        blinks: 0,
+    },
+};
+
+// Vendor request setting the rumble motors and the door lamp
+const OUTPUT_REQUEST: u8 = 0x09;
+const OUTPUT_VALUE: u16 = 0x0301;
+
 const POWER_NOTCHES: [u8; 6] = [0x81, 0x6D, 0x54, 0x3F, 0x21, 0x00];
 const BRAKE_NOTCHES: [u8; 10] = [0x79, 0x8A, 0x94, 0x9A, 0xA2, 0xA8, 0xAF, 0xB2, 0xB5, 0xB9];
 
@@ -40,62 +62,142 @@ bitflags! {
     }
 }
 
-pub fn update_gadget(state: &mut ControllerState) {
-    // Calculate data for handles
-    let power = POWER_NOTCHES[state.power as usize];
-    let brake = BRAKE_NOTCHES[state.brake as usize];
+pub struct Tcpp20009;
 
-    // Calculate data for buttons
-    let mut buttons = Buttons::NONE;
-    if state.button_a {
-        buttons.insert(Buttons::A)
-    }
-    if state.button_b {
-        buttons.insert(Buttons::B)
-    }
-    if state.button_c {
-        buttons.insert(Buttons::C)
-    }
-    if state.button_d {
-        buttons.insert(Buttons::D)
-    }
-    if state.button_select {
-        buttons.insert(Buttons::SELECT)
-    }
-    if state.button_start {
-        buttons.insert(Buttons::START)
+impl EmulatedController for Tcpp20009 {
+    fn info(&self) -> &'static ModelInfo {
+        &INFO
     }
 
-    // Calculate data for D-pad
-    let mut dpad: u8 = 0x8;
-    if state.button_up {
-        dpad = 0x0
-    }
-    if state.button_down {
-        dpad = 0x4
-    }
-    if state.button_left {
-        dpad = 0x6
+    fn control_request(&self, request: &ControlRequest) -> Control {
+        match (request.request_type, request.request, request.value) {
+            (0x40 | 0x41, OUTPUT_REQUEST, OUTPUT_VALUE) => Control::Receive,
+            _ => Control::Stall,
+        }
     }
-    if state.button_right {
-        dpad = 0x2
-    }
-    if state.button_up & state.button_left {
-        dpad = 0x7
+
+    fn control_data(&self, _request: &ControlRequest, data: &[u8], feedback: &mut Feedback) {
+        // Bytes 2 and 3 drive the right and left rumble motors, byte 4 the door lamp
+        match data {
+            [_, _, right, left, lamp, ..] => {
+                feedback.rumble = *right != 0 || *left != 0;
+                feedback.lamp = *lamp != 0;
+            }
+            _ => warn!(
+                "Ignoring TCPP-20009 output of {} bytes: {:02x?}",
+                data.len(),
+                data
+            ),
+        }
     }
-    if state.button_up & state.button_right {
-        dpad = 0x1
+
+    fn encode_report(&self, state: &mut ControllerState) -> Vec<u8> {
+        // Calculate data for handles
+        let power = POWER_NOTCHES[state.power as usize];
+        let brake = BRAKE_NOTCHES[state.brake as usize];
+
+        // Calculate data for buttons
+        let mut buttons = Buttons::NONE;
+        if state.button_a {
+            buttons.insert(Buttons::A)
+        }
+        if state.button_b {
+            buttons.insert(Buttons::B)
+        }
+        if state.button_c {
+            buttons.insert(Buttons::C)
+        }
+        if state.button_d {
+            buttons.insert(Buttons::D)
+        }
+        if state.button_select {
+            buttons.insert(Buttons::SELECT)
+        }
+        if state.button_start {
+            buttons.insert(Buttons::START)
+        }
+
+        // Calculate data for D-pad
+        let mut dpad: u8 = 0x8;
+        if state.button_up {
+            dpad = 0x0
+        }
+        if state.button_down {
+            dpad = 0x4
+        }
+        if state.button_left {
+            dpad = 0x6
+        }
+        if state.button_right {
+            dpad = 0x2
+        }
+        if state.button_up & state.button_left {
+            dpad = 0x7
+        }
+        if state.button_up & state.button_right {
+            dpad = 0x1
+        }
+        if state.button_down & state.button_left {
+            dpad = 0x5
+        }
+        if state.button_down & state.button_right {
+            dpad = 0x3
+        }
+
+        // Assemble the report
+        vec![0x1, brake, power, 0xFF, dpad, buttons.bits]
     }
-    if state.button_down & state.button_left {
-        dpad = 0x5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::emulated::ControllerModel;
+    use crate::controller::emulated::tests::{setup, transfer};
+
+    fn output(request_type: u8, data: &[u8]) -> Vec<u8> {
+        setup(
+            request_type,
+            OUTPUT_REQUEST,
+            OUTPUT_VALUE,
+            data.len() as u16,
+            data,
+        )
     }
-    if state.button_down & state.button_right {
-        dpad = 0x3
+
+    #[test]
+    fn set_lamp_and_rumble() {
+        let transfers = transfer(
+            ControllerModel(&Tcpp20009),
+            &[output(
+                0x40,
+                &[0x00, 0x00, 0xFF, 0x00, 0x01, 0x00, 0x00, 0x00],
+            )],
+        );
+        assert!(transfers.stalls.is_empty());
+        assert!(transfers.feedback.rumble);
+        assert!(transfers.feedback.lamp);
+
+        // The last request wins, the left motor alone also rumbles
+        let transfers = transfer(
+            ControllerModel(&Tcpp20009),
+            &[
+                output(0x41, &[0x00, 0x00, 0xFF, 0x00, 0x01, 0x00]),
+                output(0x41, &[0x00, 0x00, 0x00, 0x80, 0x00, 0x00]),
+            ],
+        );
+        assert!(transfers.feedback.rumble);
+        assert!(!transfers.feedback.lamp);
     }
 
-    // Assemble data and send it to endpoint
-    let data = [0x1, brake, power, 0xFF, dpad, buttons.bits];
-    if let Ok(mut file) = File::create(ENDPOINT1) {
-        file.write(&data).ok();
+    #[test]
+    fn ignore_short_output() {
+        let transfers = transfer(
+            ControllerModel(&Tcpp20009),
+            &[output(0x40, &[0x00, 0x00, 0xFF, 0xFF])],
+        );
+        assert!(transfers.stalls.is_empty());
+        assert!(!transfers.feedback.rumble);
+        assert!(!transfers.feedback.lamp);
     }
 }
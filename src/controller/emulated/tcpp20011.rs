@@ -1,8 +1,11 @@
-use std::fs::File;
-use std::io::Write;
+use std::fmt;
+use std::sync::Mutex;
 
-use crate::controller::emulated::{DeviceDescriptor, ENDPOINT1};
-use crate::controller::physical::ControllerState;
+use crate::controller::emulated::{
+    Chord, ChordButtons, Control, DeviceDescriptor, EmulatedController, Feedback, ModelInfo,
+};
+use crate::controller::gadget::ControlRequest;
+use crate::controller::physical::{ControllerState, Pattern};
 use bitflags::bitflags;
 
 pub const DESCRIPTORS: [u8; 48] = [
@@ -25,6 +28,28 @@ pub const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
     i_serial_number: "TCPP20011",
 };
 
+pub const INFO: ModelInfo = ModelInfo {
+    id: "TCPP-20011",
+    name: "TCPP-20011",
+    device_descriptor: &DEVICE_DESCRIPTOR,
+    descriptors: &DESCRIPTORS,
+    strings: &STRINGS,
+    hid_report_descriptor: None,
+    chord: Chord {
+        buttons: ChordButtons::B,
+        power: None,
+        brake: None,
+    },
+    confirmation: Pattern {
+        pulses: 5,
+        blinks: 0,
+    },
+};
+
+// Vendor request carrying the cab display and lamps
+const DISPLAY_REQUEST: u8 = 0x09;
+const DISPLAY_VALUE: u16 = 0x0301;
+
 const POWER_NOTCHES: [u8; 6] = [0x12, 0x36, 0x5A, 0x90, 0xC6, 0xFB];
 const BRAKE_NOTCHES: [u8; 10] = [0x1C, 0x38, 0x54, 0x70, 0x8B, 0xA7, 0xC3, 0xDF, 0xDF, 0xFB];
 
@@ -40,62 +65,195 @@ bitflags! {
     }
 }
 
-pub fn update_gadget(state: &mut ControllerState) {
-    // Calculate data for handles
-    let power = POWER_NOTCHES[state.power as usize];
-    let brake = BRAKE_NOTCHES[state.brake as usize];
+/// Cab display and lamps of the Shinkansen controller, as set by the game.
+#[derive(Clone, Copy, PartialEq)]
+struct Display {
+    /// Speedometer, in km/h.
+    speed: u16,
+    /// ATC speed limit, in km/h.
+    atc_limit: u16,
+    /// Lamp warning that the ATC limit is about to change.
+    atc_approach: bool,
+    /// Door lamp, forwarded to the Plug & Play.
+    door_lamp: bool,
+}
 
-    // Calculate data for buttons
-    let mut buttons = Buttons::NONE;
-    if state.button_a {
-        buttons.insert(Buttons::A)
-    }
-    if state.button_b {
-        buttons.insert(Buttons::B)
-    }
-    if state.button_c {
-        buttons.insert(Buttons::C)
-    }
-    if state.button_d {
-        buttons.insert(Buttons::D)
-    }
-    if state.button_select {
-        buttons.insert(Buttons::SELECT)
-    }
-    if state.button_start {
-        buttons.insert(Buttons::START)
+impl Display {
+    /// Parse the data of a display request.
+    ///
+    /// Byte 2 holds the ATC approach lamp, byte 3 the door lamp, bytes 4-5 the speed and
+    /// bytes 6-7 the ATC limit, both little endian.
+    fn parse(data: &[u8]) -> Option<Display> {
+        match *data {
+            [
+                _,
+                _,
+                approach,
+                door,
+                speed_low,
+                speed_high,
+                limit_low,
+                limit_high,
+                ..,
+            ] => Some(Display {
+                speed: u16::from_le_bytes([speed_low, speed_high]),
+                atc_limit: u16::from_le_bytes([limit_low, limit_high]),
+                atc_approach: approach != 0,
+                door_lamp: door != 0,
+            }),
+            _ => None,
+        }
     }
+}
 
-    // Calculate data for D-pad
-    let mut dpad: u8 = 0x8;
-    if state.button_up {
-        dpad = 0x0
-    }
-    if state.button_down {
-        dpad = 0x4
+impl fmt::Display for Display {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} km/h, ATC {} km/h{}, door lamp {}",
+            self.speed,
+            self.atc_limit,
+            if self.atc_approach {
+                " (approaching)"
+            } else {
+                ""
+            },
+            if self.door_lamp { "on" } else { "off" }
+        )
     }
-    if state.button_left {
-        dpad = 0x6
+}
+
+/// Display before the game sends any data.
+const BLANK_DISPLAY: Display = Display {
+    speed: 0,
+    atc_limit: 0,
+    atc_approach: false,
+    door_lamp: false,
+};
+
+/// Last display data sent by the game.
+static DISPLAY: Mutex<Display> = Mutex::new(BLANK_DISPLAY);
+
+pub struct Tcpp20011;
+
+impl EmulatedController for Tcpp20011 {
+    fn info(&self) -> &'static ModelInfo {
+        &INFO
     }
-    if state.button_right {
-        dpad = 0x2
+
+    fn control_request(&self, request: &ControlRequest) -> Control {
+        match (request.request_type, request.request, request.value) {
+            (0x40 | 0x41, DISPLAY_REQUEST, DISPLAY_VALUE) => Control::Receive,
+            _ => Control::Stall,
+        }
     }
-    if state.button_up & state.button_left {
-        dpad = 0x7
+
+    fn control_data(&self, _request: &ControlRequest, data: &[u8], feedback: &mut Feedback) {
+        let Some(display) = Display::parse(data) else {
+            warn!(
+                "Ignoring TCPP-20011 display data of {} bytes: {:02x?}",
+                data.len(),
+                data
+            );
+            return;
+        };
+        feedback.lamp = display.door_lamp;
+
+        // Only log changes, the speed is usually sent many times per second
+        let mut previous = DISPLAY.lock().unwrap();
+        if display.atc_limit != previous.atc_limit || display.door_lamp != previous.door_lamp {
+            info!("Shinkansen display: {}", display);
+        } else if display != *previous {
+            debug!("Shinkansen display: {}", display);
+        }
+        *previous = display;
     }
-    if state.button_up & state.button_right {
-        dpad = 0x1
+
+    fn reset(&self) {
+        *DISPLAY.lock().unwrap() = BLANK_DISPLAY;
     }
-    if state.button_down & state.button_left {
-        dpad = 0x5
+
+    fn encode_report(&self, state: &mut ControllerState) -> Vec<u8> {
+        // Calculate data for handles
+        let power = POWER_NOTCHES[state.power as usize];
+        let brake = BRAKE_NOTCHES[state.brake as usize];
+
+        // Calculate data for buttons
+        let mut buttons = Buttons::NONE;
+        if state.button_a {
+            buttons.insert(Buttons::A)
+        }
+        if state.button_b {
+            buttons.insert(Buttons::B)
+        }
+        if state.button_c {
+            buttons.insert(Buttons::C)
+        }
+        if state.button_d {
+            buttons.insert(Buttons::D)
+        }
+        if state.button_select {
+            buttons.insert(Buttons::SELECT)
+        }
+        if state.button_start {
+            buttons.insert(Buttons::START)
+        }
+
+        // Calculate data for D-pad
+        let mut dpad: u8 = 0x8;
+        if state.button_up {
+            dpad = 0x0
+        }
+        if state.button_down {
+            dpad = 0x4
+        }
+        if state.button_left {
+            dpad = 0x6
+        }
+        if state.button_right {
+            dpad = 0x2
+        }
+        if state.button_up & state.button_left {
+            dpad = 0x7
+        }
+        if state.button_up & state.button_right {
+            dpad = 0x1
+        }
+        if state.button_down & state.button_left {
+            dpad = 0x5
+        }
+        if state.button_down & state.button_right {
+            dpad = 0x3
+        }
+
+        // Assemble the report
+        vec![brake, power, 0xFF, dpad, buttons.bits, 0x0]
     }
-    if state.button_down & state.button_right {
-        dpad = 0x3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_display() {
+        let display = Display::parse(&[0x00, 0x00, 0x01, 0x00, 0x2C, 0x01, 0x0E, 0x01]).unwrap();
+        assert_eq!(display.speed, 300);
+        assert_eq!(display.atc_limit, 270);
+        assert!(display.atc_approach);
+        assert!(!display.door_lamp);
+
+        // Trailing bytes are ignored
+        let display = Display::parse(&[0, 0, 0, 1, 5, 0, 0, 0, 0xFF]).unwrap();
+        assert_eq!(display.speed, 5);
+        assert_eq!(display.atc_limit, 0);
+        assert!(!display.atc_approach);
+        assert!(display.door_lamp);
     }
 
-    // Assemble data and send it to endpoint
-    let data = [brake, power, 0xFF, dpad, buttons.bits, 0x0];
-    if let Ok(mut file) = File::create(ENDPOINT1) {
-        file.write(&data).ok();
+    #[test]
+    fn reject_short_display() {
+        assert!(Display::parse(&[0; 7]).is_none());
+        assert!(Display::parse(&[]).is_none());
     }
 }
@@ -1,8 +1,12 @@
-use crate::controller::emulated::{DeviceDescriptor, ENDPOINT1};
-use crate::controller::physical::ControllerState;
+use std::sync::Mutex;
+
+use crate::controller::emulated::{
+    Chord, ChordButtons, Control, DeviceDescriptor, EmulatedController, Feedback, HID_GET_REPORT,
+    HID_REPORT_FEATURE, HID_REPORT_OUTPUT, HID_SET_REPORT, ModelInfo,
+};
+use crate::controller::gadget::ControlRequest;
+use crate::controller::physical::{ControllerState, Pattern};
 use bitflags::bitflags;
-use std::fs::File;
-use std::io::Write;
 
 pub const DESCRIPTORS: [u8; 80] = [
     0x01, 0x00, 0x00, 0x00, 0x50, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00,
@@ -26,6 +30,24 @@ pub const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
     i_serial_number: "ZKNS-001",
 };
 
+pub const INFO: ModelInfo = ModelInfo {
+    id: "ZKNS-001",
+    name: "ZKNS-001",
+    device_descriptor: &DEVICE_DESCRIPTOR,
+    descriptors: &DESCRIPTORS,
+    strings: &STRINGS,
+    hid_report_descriptor: Some(&HID_REPORT_DESCRIPTOR),
+    chord: Chord {
+        buttons: ChordButtons::UP,
+        power: None,
+        brake: None,
+    },
+    confirmation: Pattern {
+        pulses: 1,
+        blinks: 0,
+    },
+};
+
 pub const HID_REPORT_DESCRIPTOR: [u8; 94] = [
     0x05, 0x01, // Usage Page (Generic Desktop Ctrls)
     0x09, 0x05, // Usage (Game Pad)
@@ -76,6 +98,15 @@ pub const HID_REPORT_DESCRIPTOR: [u8; 94] = [
     0xC0, // End Collection
 ];
 
+/// Size of the feature and output reports under usage 0x484F.
+const VENDOR_REPORT_SIZE: usize = 8;
+
+/// Last feature report set by the host, sent back when it asks for it.
+static FEATURE_REPORT: Mutex<[u8; VENDOR_REPORT_SIZE]> = Mutex::new([0; VENDOR_REPORT_SIZE]);
+
+/// Last output report sent by the host, to only log changes.
+static OUTPUT_REPORT: Mutex<[u8; VENDOR_REPORT_SIZE]> = Mutex::new([0; VENDOR_REPORT_SIZE]);
+
 const POWER_NOTCHES: [u8; 6] = [0x80, 0x9F, 0xB7, 0xCE, 0xE6, 0xFF];
 const BRAKE_NOTCHES: [u8; 10] = [0x80, 0x65, 0x57, 0x49, 0x3C, 0x2E, 0x20, 0x13, 0x05, 0x00];
 
@@ -98,89 +129,200 @@ bitflags! {
     }
 }
 
-pub fn update_gadget(state: &mut ControllerState) {
-    // Calculate data for handles
-    let mut handle = POWER_NOTCHES[state.power as usize];
-    if state.brake > 0 {
-        handle = BRAKE_NOTCHES[state.brake as usize];
-    }
+pub struct Zkns001;
 
-    // Calculate data for buttons
-    let mut buttons1 = Buttons1::NONE;
-    let mut buttons2 = Buttons2::NONE;
-    if !state.button_select_hold && state.button_select && state.button_left {
-        buttons1.insert(Buttons1::L);
-        state.combo = true;
-    }
-    if !state.button_select_hold && state.button_select && state.button_right {
-        buttons1.insert(Buttons1::R);
-        state.combo = true;
-    }
-    if !state.button_select_hold && state.button_start && state.button_select {
-        buttons2.insert(Buttons2::HOME);
-        state.combo = true;
-    }
-    if state.button_a {
-        buttons1.insert(Buttons1::Y)
-    }
-    if state.button_b {
-        buttons1.insert(Buttons1::B)
-    }
-    if state.button_c {
-        buttons1.insert(Buttons1::A)
-    }
-    if state.button_d {
-        buttons1.insert(Buttons1::X)
-    }
-    if state.brake == 9 {
-        buttons1.insert(Buttons1::ZL)
-    }
-    if !state.combo && state.button_start {
-        buttons2.insert(Buttons2::START)
-    }
-    if !state.combo && state.button_select_hold {
-        buttons2.insert(Buttons2::SELECT)
+/// Keep a feature or output report sent by the host in `last`.
+///
+/// The layout of the vendor reports is not known, so they are only checked for their size.
+/// Their payload is logged when it changes, as the host may send the same report repeatedly.
+fn receive_vendor_report(kind: &str, data: &[u8], last: &Mutex<[u8; VENDOR_REPORT_SIZE]>) {
+    let Ok(report) = <[u8; VENDOR_REPORT_SIZE]>::try_from(data) else {
+        warn!(
+            "Ignoring ZKNS-001 {} report of {} bytes: {:02x?}",
+            kind,
+            data.len(),
+            data
+        );
+        return;
+    };
+    let mut last = last.lock().unwrap();
+    if report != *last {
+        info!(
+            "ZKNS-001 {} report of unknown layout: {:02x?}",
+            kind, report
+        );
+    } else {
+        trace!("Unchanged ZKNS-001 {} report", kind);
     }
+    *last = report;
+}
 
-    // Calculate data for D-pad
-    let mut dpad: u8 = 0xF;
-    if state.button_up {
-        dpad = 0x0
+impl EmulatedController for Zkns001 {
+    fn info(&self) -> &'static ModelInfo {
+        &INFO
     }
-    if state.button_down {
-        dpad = 0x4
+
+    fn control_request(&self, request: &ControlRequest) -> Control {
+        match (request.request_type, request.request, request.value >> 8) {
+            (0xA1, HID_GET_REPORT, HID_REPORT_FEATURE) => {
+                Control::Reply(FEATURE_REPORT.lock().unwrap().to_vec())
+            }
+            (0x21, HID_SET_REPORT, HID_REPORT_OUTPUT | HID_REPORT_FEATURE) => Control::Receive,
+            _ => Control::Stall,
+        }
     }
-    if !state.combo && state.button_left {
-        dpad = 0x6
+
+    fn control_data(&self, request: &ControlRequest, data: &[u8], _feedback: &mut Feedback) {
+        if request.value >> 8 == HID_REPORT_FEATURE {
+            receive_vendor_report("feature", data, &FEATURE_REPORT);
+        } else {
+            receive_vendor_report("output", data, &OUTPUT_REPORT);
+        }
     }
-    if !state.combo && state.button_right {
-        dpad = 0x2
+
+    fn has_out_endpoint(&self) -> bool {
+        true
     }
-    if !state.combo && state.button_up & state.button_left {
-        dpad = 0x7
+
+    fn out_data(&self, data: &[u8], _feedback: &mut Feedback) {
+        receive_vendor_report("output", data, &OUTPUT_REPORT);
     }
-    if !state.combo && state.button_up & state.button_right {
-        dpad = 0x1
+
+    fn reset(&self) {
+        *FEATURE_REPORT.lock().unwrap() = [0; VENDOR_REPORT_SIZE];
+        *OUTPUT_REPORT.lock().unwrap() = [0; VENDOR_REPORT_SIZE];
     }
-    if !state.combo && state.button_down & state.button_left {
-        dpad = 0x5
+
+    fn encode_report(&self, state: &mut ControllerState) -> Vec<u8> {
+        // Calculate data for handles
+        let mut handle = POWER_NOTCHES[state.power as usize];
+        if state.brake > 0 {
+            handle = BRAKE_NOTCHES[state.brake as usize];
+        }
+
+        // Calculate data for buttons
+        let mut buttons1 = Buttons1::NONE;
+        let mut buttons2 = Buttons2::NONE;
+        if !state.button_select_hold && state.button_select && state.button_left {
+            buttons1.insert(Buttons1::L);
+            state.combo = true;
+        }
+        if !state.button_select_hold && state.button_select && state.button_right {
+            buttons1.insert(Buttons1::R);
+            state.combo = true;
+        }
+        if !state.button_select_hold && state.button_start && state.button_select {
+            buttons2.insert(Buttons2::HOME);
+            state.combo = true;
+        }
+        if state.button_a {
+            buttons1.insert(Buttons1::Y)
+        }
+        if state.button_b {
+            buttons1.insert(Buttons1::B)
+        }
+        if state.button_c {
+            buttons1.insert(Buttons1::A)
+        }
+        if state.button_d {
+            buttons1.insert(Buttons1::X)
+        }
+        if state.brake == 9 {
+            buttons1.insert(Buttons1::ZL)
+        }
+        if !state.combo && state.button_start {
+            buttons2.insert(Buttons2::START)
+        }
+        if !state.combo && state.button_select_hold {
+            buttons2.insert(Buttons2::SELECT)
+        }
+
+        // Calculate data for D-pad
+        let mut dpad: u8 = 0xF;
+        if state.button_up {
+            dpad = 0x0
+        }
+        if state.button_down {
+            dpad = 0x4
+        }
+        if !state.combo && state.button_left {
+            dpad = 0x6
+        }
+        if !state.combo && state.button_right {
+            dpad = 0x2
+        }
+        if !state.combo && state.button_up & state.button_left {
+            dpad = 0x7
+        }
+        if !state.combo && state.button_up & state.button_right {
+            dpad = 0x1
+        }
+        if !state.combo && state.button_down & state.button_left {
+            dpad = 0x5
+        }
+        if !state.combo && state.button_down & state.button_right {
+            dpad = 0x3
+        }
+
+        // Assemble the report
+        vec![
+            buttons1.bits,
+            buttons2.bits,
+            dpad,
+            0x80,
+            handle,
+            0x80,
+            0x80,
+            0x00,
+        ]
     }
-    if !state.combo && state.button_down & state.button_right {
-        dpad = 0x3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::emulated::ControllerModel;
+    use crate::controller::emulated::tests::{setup, transfer};
+
+    const FEATURE: u16 = HID_REPORT_FEATURE << 8;
+
+    #[test]
+    fn return_feature_report_set_by_host() {
+        let report = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        let transfers = transfer(
+            ControllerModel(&Zkns001),
+            &[
+                setup(0xA1, HID_GET_REPORT, FEATURE, 8, &[]),
+                setup(0x21, HID_SET_REPORT, FEATURE, 8, &report),
+                // Reports of the wrong size are ignored
+                setup(0x21, HID_SET_REPORT, FEATURE, 2, &[0xFF, 0xFF]),
+                setup(0xA1, HID_GET_REPORT, FEATURE, 8, &[]),
+            ],
+        );
+        assert!(transfers.stalls.is_empty());
+        assert_eq!(transfers.replies, [[0; 8], report].concat());
+
+        // A new session starts over
+        let transfers = transfer(
+            ControllerModel(&Zkns001),
+            &[setup(0xA1, HID_GET_REPORT, FEATURE, 8, &[])],
+        );
+        assert_eq!(transfers.replies, [0; 8]);
     }
 
-    // Assemble data and send it to gadget
-    let data = [
-        buttons1.bits,
-        buttons2.bits,
-        dpad,
-        0x80,
-        handle,
-        0x80,
-        0x80,
-        0x00,
-    ];
-    if let Ok(mut file) = File::create(ENDPOINT1) {
-        file.write(&data).ok();
+    #[test]
+    fn accept_output_report() {
+        let transfers = transfer(
+            ControllerModel(&Zkns001),
+            &[setup(
+                0x21,
+                HID_SET_REPORT,
+                HID_REPORT_OUTPUT << 8,
+                8,
+                &[0x01; 8],
+            )],
+        );
+        assert!(transfers.stalls.is_empty());
+        assert!(transfers.replies.is_empty());
     }
 }
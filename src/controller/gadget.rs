@@ -0,0 +1,303 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::controller::emulated::DeviceDescriptor;
+use crate::error::{Error, Result};
+
+mod directory;
+mod functionfs;
+
+pub(crate) use directory::DirectoryGadget;
+use functionfs::FunctionFsGadget;
+
+// FunctionFS event types
+const FUNCTIONFS_BIND: u8 = 0;
+const FUNCTIONFS_UNBIND: u8 = 1;
+const FUNCTIONFS_ENABLE: u8 = 2;
+const FUNCTIONFS_DISABLE: u8 = 3;
+const FUNCTIONFS_SETUP: u8 = 4;
+const FUNCTIONFS_SUSPEND: u8 = 5;
+const FUNCTIONFS_RESUME: u8 = 6;
+
+/// Size of a `usb_functionfs_event` read from EP0.
+pub const EVENT_SIZE: usize = 12;
+
+/// Setup packet of a control transfer.
+#[derive(Debug, Clone, Copy)]
+pub struct ControlRequest {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl ControlRequest {
+    /// Whether the data stage goes from the device to the host.
+    pub fn is_in(&self) -> bool {
+        self.request_type & 0x80 != 0
+    }
+
+    /// Raw setup packet, as found at the start of a SETUP event.
+    fn to_bytes(self) -> [u8; 8] {
+        let [value_low, value_high] = self.value.to_le_bytes();
+        let [index_low, index_high] = self.index.to_le_bytes();
+        let [length_low, length_high] = self.length.to_le_bytes();
+        [
+            self.request_type,
+            self.request,
+            value_low,
+            value_high,
+            index_low,
+            index_high,
+            length_low,
+            length_high,
+        ]
+    }
+}
+
+/// Event sent by FunctionFS on EP0.
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    Bind,
+    Unbind,
+    Enable,
+    Disable,
+    Setup(ControlRequest),
+    Suspend,
+    Resume,
+}
+
+impl Event {
+    /// Parse a `usb_functionfs_event`, failing on unknown event types.
+    pub fn parse(buffer: &[u8; EVENT_SIZE]) -> io::Result<Event> {
+        match buffer[8] {
+            FUNCTIONFS_BIND => Ok(Event::Bind),
+            FUNCTIONFS_UNBIND => Ok(Event::Unbind),
+            FUNCTIONFS_ENABLE => Ok(Event::Enable),
+            FUNCTIONFS_DISABLE => Ok(Event::Disable),
+            FUNCTIONFS_SETUP => Ok(Event::Setup(ControlRequest {
+                request_type: buffer[0],
+                request: buffer[1],
+                value: u16::from_le_bytes([buffer[2], buffer[3]]),
+                index: u16::from_le_bytes([buffer[4], buffer[5]]),
+                length: u16::from_le_bytes([buffer[6], buffer[7]]),
+            })),
+            FUNCTIONFS_SUSPEND => Ok(Event::Suspend),
+            FUNCTIONFS_RESUME => Ok(Event::Resume),
+            kind => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown FunctionFS event type {}", kind),
+            )),
+        }
+    }
+
+    /// Raw `usb_functionfs_event`, as read from EP0.
+    pub(crate) fn to_bytes(self) -> [u8; EVENT_SIZE] {
+        let mut buffer = [0; EVENT_SIZE];
+        buffer[8] = match self {
+            Event::Bind => FUNCTIONFS_BIND,
+            Event::Unbind => FUNCTIONFS_UNBIND,
+            Event::Enable => FUNCTIONFS_ENABLE,
+            Event::Disable => FUNCTIONFS_DISABLE,
+            Event::Setup(request) => {
+                buffer[..8].copy_from_slice(&request.to_bytes());
+                FUNCTIONFS_SETUP
+            }
+            Event::Suspend => FUNCTIONFS_SUSPEND,
+            Event::Resume => FUNCTIONFS_RESUME,
+        };
+        buffer
+    }
+}
+
+/// Connection state of the function with the host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GadgetState {
+    /// Not bound to the USB device controller.
+    Unbound,
+    /// Bound, waiting for the host to select a configuration.
+    Bound,
+    /// Configured by the host, endpoints can be used.
+    Enabled,
+    /// Suspended by the host, remembering whether the function was enabled.
+    Suspended { enabled: bool },
+}
+
+impl GadgetState {
+    /// State after the event, which is the same for control transfers.
+    pub fn next(self, event: &Event) -> GadgetState {
+        match (self, event) {
+            (_, Event::Bind) => GadgetState::Bound,
+            (_, Event::Unbind) => GadgetState::Unbound,
+            // FunctionFS merges queued events, so BIND can be lost before ENABLE
+            (_, Event::Enable) => GadgetState::Enabled,
+            (GadgetState::Unbound, _) => GadgetState::Unbound,
+            (_, Event::Disable) => GadgetState::Bound,
+            (GadgetState::Suspended { .. }, Event::Suspend) => self,
+            (_, Event::Suspend) => GadgetState::Suspended {
+                enabled: self == GadgetState::Enabled,
+            },
+            (GadgetState::Suspended { enabled: true }, Event::Resume) => GadgetState::Enabled,
+            (GadgetState::Suspended { enabled: false }, Event::Resume) => GadgetState::Bound,
+            _ => self,
+        }
+    }
+}
+
+impl fmt::Display for GadgetState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GadgetState::Unbound => write!(f, "unbound"),
+            GadgetState::Bound => write!(f, "bound"),
+            GadgetState::Enabled => write!(f, "enabled"),
+            GadgetState::Suspended { .. } => write!(f, "suspended"),
+        }
+    }
+}
+
+/// USB gadget used to present the emulated controller to the host.
+pub trait Gadget: Send + Sync {
+    /// Set up the gadget with the device descriptor and FunctionFS descriptors and strings.
+    fn init(&self, device: &DeviceDescriptor, descriptors: &[u8], strings: &[u8]) -> Result<()>;
+
+    /// Wait up to `timeout` for the next FunctionFS event on EP0, `None` if none arrived.
+    fn read_event(&self, timeout: Duration) -> io::Result<Option<Event>>;
+
+    /// Send the data stage of a device-to-host control transfer on EP0.
+    fn write_ep0(&self, data: &[u8]) -> Result<()>;
+
+    /// Receive up to `length` bytes of a host-to-device control transfer on EP0, acknowledging it.
+    fn read_ep0(&self, length: usize) -> Result<Vec<u8>>;
+
+    /// Reject the pending control transfer by stalling EP0.
+    fn stall_ep0(&self, request: &ControlRequest) -> Result<()>;
+
+    /// Send an input report on EP1, blocking until the host reads it or the thread is interrupted.
+    fn write_ep1(&self, data: &[u8]) -> Result<()>;
+
+    /// Receive data sent by the host on EP2, blocking until it arrives or the thread is interrupted.
+    fn read_ep2(&self, buffer: &mut [u8]) -> Result<usize>;
+
+    /// Close the endpoints and undo the setup, warning about steps that fail.
+    fn close(&self);
+}
+
+/// Open the gadget selected by the `DDGO_PNP_GADGET` environment variable.
+///
+/// Accepted values are `functionfs` (default) and `directory:<path>`.
+pub fn init() -> Result<Arc<dyn Gadget>> {
+    let spec = env::var("DDGO_PNP_GADGET").unwrap_or_default();
+    match spec.as_str() {
+        "" | "functionfs" => Ok(Arc::new(FunctionFsGadget::new())),
+        _ => match spec.strip_prefix("directory:") {
+            Some(path) => Ok(Arc::new(DirectoryGadget::new(path))),
+            None => Err(Error::Config(format!("unknown gadget '{}'", spec))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(bytes: [u8; 8], kind: u8) -> io::Result<Event> {
+        let mut buffer = [0; EVENT_SIZE];
+        buffer[..8].copy_from_slice(&bytes);
+        buffer[8] = kind;
+        Event::parse(&buffer)
+    }
+
+    #[test]
+    fn parse_state_events() {
+        for kind in [
+            Event::Bind,
+            Event::Unbind,
+            Event::Enable,
+            Event::Disable,
+            Event::Suspend,
+            Event::Resume,
+        ] {
+            let parsed = Event::parse(&kind.to_bytes()).unwrap();
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", kind));
+        }
+    }
+
+    #[test]
+    fn parse_setup_event() {
+        let parsed = event(
+            [0xA1, 0x01, 0x00, 0x03, 0x02, 0x00, 0x08, 0x00],
+            FUNCTIONFS_SETUP,
+        );
+        match parsed.unwrap() {
+            Event::Setup(request) => {
+                assert_eq!(request.request_type, 0xA1);
+                assert_eq!(request.request, 0x01);
+                assert_eq!(request.value, 0x0300);
+                assert_eq!(request.index, 2);
+                assert_eq!(request.length, 8);
+                assert!(request.is_in());
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn setup_event_round_trip() {
+        let bytes = [0x21, 0x09, 0x00, 0x03, 0x01, 0x00, 0x08, 0x00];
+        let Event::Setup(request) = event(bytes, FUNCTIONFS_SETUP).unwrap() else {
+            panic!("not a setup event");
+        };
+        assert_eq!(Event::Setup(request).to_bytes()[..8], bytes);
+    }
+
+    #[test]
+    fn parse_unknown_event() {
+        let error = event([0; 8], 7).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn follow_host_connection() {
+        let mut state = GadgetState::Unbound;
+        for (event, expected) in [
+            (Event::Bind, GadgetState::Bound),
+            (Event::Enable, GadgetState::Enabled),
+            (Event::Suspend, GadgetState::Suspended { enabled: true }),
+            (Event::Suspend, GadgetState::Suspended { enabled: true }),
+            (Event::Resume, GadgetState::Enabled),
+            (Event::Disable, GadgetState::Bound),
+            (Event::Suspend, GadgetState::Suspended { enabled: false }),
+            (Event::Resume, GadgetState::Bound),
+            (Event::Unbind, GadgetState::Unbound),
+        ] {
+            state = state.next(&event);
+            assert_eq!(state, expected, "after {:?}", event);
+        }
+    }
+
+    #[test]
+    fn enable_without_bind() {
+        assert_eq!(
+            GadgetState::Unbound.next(&Event::Enable),
+            GadgetState::Enabled
+        );
+        assert_eq!(
+            GadgetState::Suspended { enabled: false }.next(&Event::Enable),
+            GadgetState::Enabled
+        );
+    }
+
+    #[test]
+    fn ignore_events_while_unbound() {
+        for event in [Event::Disable, Event::Suspend, Event::Resume] {
+            assert_eq!(GadgetState::Unbound.next(&event), GadgetState::Unbound);
+        }
+        let setup = event([0; 8], FUNCTIONFS_SETUP).unwrap();
+        assert_eq!(GadgetState::Enabled.next(&setup), GadgetState::Enabled);
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::controller::emulated::DeviceDescriptor;
+use crate::controller::gadget::{ControlRequest, EVENT_SIZE, Event, Gadget};
+use crate::error::{Error, Result};
+
+/// Stand-in gadget that records the USB traffic in plain files.
+///
+/// `device` holds the device descriptor, `ep0` the FunctionFS descriptors and strings
+/// followed by control transfer replies, and `ep1` every input report in order.
+/// Data sent by the host on the OUT endpoint is read from `ep2` if it exists.
+/// Raw FunctionFS events to be handled are read from `events` if it exists, otherwise
+/// the host binds and enables the function right away. The data stage of host-to-device
+/// control transfers follows their SETUP event in `events`. The setup packets of stalled
+/// control transfers are appended to `stalls`.
+pub struct DirectoryGadget {
+    path: PathBuf,
+    events: Mutex<Option<Box<dyn Read + Send>>>,
+    ep1: Mutex<Option<File>>,
+    ep2: Mutex<Option<File>>,
+}
+
+impl DirectoryGadget {
+    pub fn new(path: &str) -> DirectoryGadget {
+        DirectoryGadget {
+            path: PathBuf::from(path),
+            events: Mutex::new(None),
+            ep1: Mutex::new(None),
+            ep2: Mutex::new(None),
+        }
+    }
+}
+
+impl Gadget for DirectoryGadget {
+    fn init(&self, device: &DeviceDescriptor, descriptors: &[u8], strings: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.path).map_err(Error::DescriptorWrite)?;
+        fs::write(
+            self.path.join("device"),
+            format!(
+                "bDeviceClass={:x}\nbDeviceSubClass={:x}\nidVendor={:x}\nidProduct={:x}\n\
+                 bcdDevice={:x}\niManufacturer={}\niProduct={}\niSerialNumber={}\n",
+                device.b_device_class,
+                device.b_device_sub_class,
+                device.id_vendor,
+                device.id_product,
+                device.bcd_device,
+                device.i_manufacturer,
+                device.i_product,
+                device.i_serial_number
+            ),
+        )
+        .map_err(Error::DescriptorWrite)?;
+        fs::write(self.path.join("ep0"), [descriptors, strings].concat())
+            .map_err(Error::DescriptorWrite)?;
+        *self.ep1.lock().unwrap() =
+            Some(File::create(self.path.join("ep1")).map_err(Error::DescriptorWrite)?);
+        fs::write(self.path.join("stalls"), []).map_err(Error::DescriptorWrite)?;
+        *self.ep2.lock().unwrap() = File::open(self.path.join("ep2")).ok();
+        *self.events.lock().unwrap() = Some(match File::open(self.path.join("events")) {
+            Ok(events) => Box::new(events),
+            Err(_) => Box::new(Cursor::new(
+                [Event::Bind.to_bytes(), Event::Enable.to_bytes()].concat(),
+            )),
+        });
+        Ok(())
+    }
+
+    fn read_event(&self, timeout: Duration) -> io::Result<Option<Event>> {
+        let mut buffer = [0; EVENT_SIZE];
+        let result = match self.events.lock().unwrap().as_mut() {
+            Some(events) => events.read_exact(&mut buffer),
+            None => return Err(io::Error::from(ErrorKind::NotConnected)),
+        };
+        match result {
+            Ok(()) => Event::parse(&buffer).map(Some),
+            // The host stays quiet once all events are handled
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                sleep(timeout);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_ep0(&self, data: &[u8]) -> Result<()> {
+        OpenOptions::new()
+            .append(true)
+            .open(self.path.join("ep0"))
+            .and_then(|mut ep0| ep0.write_all(data))
+            .map_err(|e| Error::EndpointWrite(0, e))
+    }
+
+    fn read_ep0(&self, length: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; length];
+        match self.events.lock().unwrap().as_mut() {
+            Some(events) => events.read_exact(&mut data),
+            None => Err(io::Error::from(ErrorKind::NotConnected)),
+        }
+        .map_err(|e| Error::EndpointRead(0, e))?;
+        Ok(data)
+    }
+
+    fn stall_ep0(&self, request: &ControlRequest) -> Result<()> {
+        // Skip the data of the rejected request to keep reading events
+        if !request.is_in() {
+            self.read_ep0(request.length as usize)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path.join("stalls"))
+            .and_then(|mut stalls| stalls.write_all(&request.to_bytes()))
+            .map_err(|e| Error::EndpointWrite(0, e))
+    }
+
+    fn write_ep1(&self, data: &[u8]) -> Result<()> {
+        match self.ep1.lock().unwrap().as_mut() {
+            Some(ep1) => ep1.write_all(data),
+            None => Err(io::Error::from(ErrorKind::NotConnected)),
+        }
+        .map_err(|e| Error::EndpointWrite(1, e))
+    }
+
+    fn read_ep2(&self, buffer: &mut [u8]) -> Result<usize> {
+        match self.ep2.lock().unwrap().as_mut() {
+            Some(ep2) => ep2.read(buffer),
+            None => Err(io::Error::from(ErrorKind::NotConnected)),
+        }
+        .map_err(|e| Error::EndpointRead(2, e))
+    }
+
+    fn close(&self) {
+        *self.ep2.lock().unwrap() = None;
+        *self.ep1.lock().unwrap() = None;
+        *self.events.lock().unwrap() = None;
+    }
+}
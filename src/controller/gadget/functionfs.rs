@@ -0,0 +1,275 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::libc::O_NONBLOCK;
+use nix::poll::{PollFd, PollFlags, poll};
+
+use crate::controller::emulated::DeviceDescriptor;
+use crate::controller::gadget::{ControlRequest, EVENT_SIZE, Event, Gadget};
+use crate::error::{Error, Result};
+
+const FFS_MOUNT: &str = "/tmp/ffs";
+const ENDPOINT0: &str = "/tmp/ffs/ep0";
+const ENDPOINT1: &str = "/tmp/ffs/ep1";
+const ENDPOINT2: &str = "/tmp/ffs/ep2";
+const ANDROID_GADGET: &str = "/sys/class/android_usb/android0";
+
+/// Attempts to unmount FunctionFS while the last EP0 reader lets go of it.
+const UNMOUNT_ATTEMPTS: u32 = 10;
+
+/// The g_ffs FunctionFS gadget of the Plug & Play.
+///
+/// Endpoints are shared so they can be closed while a read or write is blocked on them.
+/// EP0 is read without blocking so the event thread can notice the session closing.
+pub struct FunctionFsGadget {
+    ep0: Mutex<Option<Arc<File>>>,
+    ep1: Mutex<Option<Arc<File>>>,
+    ep2: Mutex<Option<Arc<File>>>,
+}
+
+impl FunctionFsGadget {
+    pub fn new() -> FunctionFsGadget {
+        FunctionFsGadget {
+            ep0: Mutex::new(None),
+            ep1: Mutex::new(None),
+            ep2: Mutex::new(None),
+        }
+    }
+}
+
+impl Gadget for FunctionFsGadget {
+    fn init(&self, device: &DeviceDescriptor, descriptors: &[u8], strings: &[u8]) -> Result<()> {
+        // Init g_ffs kernel module
+        run(Command::new("modprobe")
+            .arg("g_ffs")
+            .arg(String::from("bDeviceClass=") + &device.b_device_class.to_string())
+            .arg(String::from("bDeviceSubClass=") + &device.b_device_sub_class.to_string())
+            .arg(String::from("idVendor=") + &device.id_vendor.to_string())
+            .arg(String::from("idProduct=") + &device.id_product.to_string())
+            .arg(String::from("bcdDevice=") + &device.bcd_device.to_string())
+            .arg(String::from("iManufacturer=") + device.i_manufacturer)
+            .arg(String::from("iProduct=") + device.i_product)
+            .arg(String::from("iSerialNumber=") + device.i_serial_number))
+        .map_err(Error::Modprobe)?;
+        if !Path::new(ENDPOINT0).exists() {
+            fs::create_dir_all(FFS_MOUNT).map_err(|e| Error::Mount(e.to_string()))?;
+            run(Command::new("mount").args(["-t", "functionfs", "ffs", FFS_MOUNT]))
+                .map_err(Error::Mount)?;
+        }
+
+        // Keep EP0 open for events, FunctionFS resets when the last handle is closed
+        *self.ep0.lock().unwrap() = Some(Arc::new(
+            OpenOptions::new()
+                .read(true)
+                .custom_flags(O_NONBLOCK)
+                .open(ENDPOINT0)
+                .map_err(Error::DescriptorWrite)?,
+        ));
+        let mut ep0 = File::create(ENDPOINT0).map_err(Error::DescriptorWrite)?;
+        ep0.write_all(descriptors).map_err(Error::DescriptorWrite)?;
+        debug!("Descriptors written to EP0");
+        ep0.write_all(strings).map_err(Error::DescriptorWrite)?;
+        debug!("Strings written to EP0");
+
+        // Init Android Gadget for old 3.4 kernel
+        let gadget = Path::new(ANDROID_GADGET);
+        if gadget.is_dir() {
+            enable_android_gadget(gadget, device).map_err(Error::GadgetEnable)?;
+        }
+        Ok(())
+    }
+
+    fn read_event(&self, timeout: Duration) -> io::Result<Option<Event>> {
+        let ep0 = match self.ep0.lock().unwrap().clone() {
+            Some(ep0) => ep0,
+            None => return Err(io::Error::from(ErrorKind::NotConnected)),
+        };
+        let mut fds = [PollFd::new(ep0.as_raw_fd(), PollFlags::POLLIN)];
+        if poll(&mut fds, timeout.as_millis() as i32)? == 0 {
+            return Ok(None);
+        }
+        let mut buffer = [0; EVENT_SIZE];
+        match ep0.as_ref().read(&mut buffer) {
+            Ok(EVENT_SIZE) => (),
+            Ok(length) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Incomplete event of {} bytes", length),
+                ));
+            }
+            // Old kernels cannot poll EP0 and always report it readable
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                sleep(timeout);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        }
+        let event = Event::parse(&buffer)?;
+
+        // Endpoints can only be used while the function is enabled
+        match event {
+            Event::Enable => {
+                *self.ep1.lock().unwrap() = OpenOptions::new()
+                    .write(true)
+                    .open(ENDPOINT1)
+                    .ok()
+                    .map(Arc::new);
+                // Only models with an OUT endpoint have EP2
+                *self.ep2.lock().unwrap() = File::open(ENDPOINT2).ok().map(Arc::new);
+            }
+            Event::Unbind | Event::Disable => {
+                *self.ep1.lock().unwrap() = None;
+                *self.ep2.lock().unwrap() = None;
+            }
+            _ => (),
+        }
+        Ok(Some(event))
+    }
+
+    fn write_ep0(&self, data: &[u8]) -> Result<()> {
+        File::create(ENDPOINT0)
+            .and_then(|mut ep0| ep0.write_all(data))
+            .map_err(|e| Error::EndpointWrite(0, e))
+    }
+
+    fn read_ep0(&self, length: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; length];
+        let length = File::open(ENDPOINT0)
+            .and_then(|mut ep0| ep0.read(&mut data))
+            .map_err(|e| Error::EndpointRead(0, e))?;
+        data.truncate(length);
+        Ok(data)
+    }
+
+    fn stall_ep0(&self, request: &ControlRequest) -> Result<()> {
+        // FunctionFS stalls when the data stage is used in the wrong direction
+        let result = if request.is_in() {
+            File::open(ENDPOINT0)
+                .and_then(|mut ep0| ep0.read(&mut []))
+                .map_err(|e| Error::EndpointRead(0, e))
+        } else {
+            File::create(ENDPOINT0)
+                .and_then(|mut ep0| ep0.write(&[]))
+                .map_err(|e| Error::EndpointWrite(0, e))
+        };
+        match result {
+            Err(Error::EndpointRead(_, e) | Error::EndpointWrite(_, e))
+                if e.raw_os_error() == Some(Errno::EL2HLT as i32) =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(e),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    fn write_ep1(&self, data: &[u8]) -> Result<()> {
+        let ep1 = self.ep1.lock().unwrap().clone();
+        // Write the report at once so an interrupted transfer is not restarted
+        match ep1 {
+            Some(ep1) => ep1.as_ref().write(data).and_then(|length| {
+                if length == data.len() {
+                    Ok(())
+                } else {
+                    Err(io::Error::from(ErrorKind::WriteZero))
+                }
+            }),
+            None => Err(io::Error::from(ErrorKind::NotConnected)),
+        }
+        .map_err(|e| Error::EndpointWrite(1, e))
+    }
+
+    fn read_ep2(&self, buffer: &mut [u8]) -> Result<usize> {
+        let ep2 = self.ep2.lock().unwrap().clone();
+        match ep2 {
+            Some(ep2) => ep2.as_ref().read(buffer),
+            None => Err(io::Error::from(ErrorKind::NotConnected)),
+        }
+        .map_err(|e| Error::EndpointRead(2, e))
+    }
+
+    fn close(&self) {
+        // Session threads are stopped by now, so no endpoint keeps FunctionFS busy
+        *self.ep2.lock().unwrap() = None;
+        *self.ep1.lock().unwrap() = None;
+        *self.ep0.lock().unwrap() = None;
+
+        // Disable Android Gadget
+        let gadget = Path::new(ANDROID_GADGET);
+        if gadget.is_dir()
+            && let Err(e) = fs::write(gadget.join(Path::new("enable")), "0")
+        {
+            warn!("Could not disable Android gadget: {}", e);
+        }
+
+        // Unmount FunctionFS and unload g_ffs kernel module
+        if Path::new(ENDPOINT0).exists() {
+            let mut attempts = 0;
+            while let Err(e) = run(Command::new("umount").arg(FFS_MOUNT)) {
+                attempts += 1;
+                if attempts == UNMOUNT_ATTEMPTS {
+                    warn!("Could not unmount FunctionFS: {}", e);
+                    break;
+                }
+                sleep(Duration::from_millis(20));
+            }
+        }
+        if let Err(e) = run(Command::new("rmmod").arg("g_ffs")) {
+            warn!("Could not unload g_ffs module: {}", e);
+        }
+    }
+}
+
+/// Run a command, returning its error output if it fails.
+fn run(command: &mut Command) -> std::result::Result<(), String> {
+    let output = command.output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+fn enable_android_gadget(gadget: &Path, device: &DeviceDescriptor) -> io::Result<()> {
+    // The RNDIS gadget may still be enabled after leaving controller mode
+    fs::write(gadget.join(Path::new("enable")), "0")?;
+    fs::write(
+        gadget.join(Path::new("bDeviceClass")),
+        device.b_device_class.to_string(),
+    )?;
+    fs::write(
+        gadget.join(Path::new("bDeviceSubClass")),
+        device.b_device_sub_class.to_string(),
+    )?;
+    fs::write(
+        gadget.join(Path::new("idVendor")),
+        format!("{:x}", device.id_vendor),
+    )?;
+    fs::write(
+        gadget.join(Path::new("idProduct")),
+        format!("{:x}", device.id_product),
+    )?;
+    fs::write(
+        gadget.join(Path::new("bcdDevice")),
+        format!("{:x}", device.bcd_device),
+    )?;
+    fs::write(
+        gadget.join(Path::new("iManufacturer")),
+        device.i_manufacturer,
+    )?;
+    fs::write(gadget.join(Path::new("iProduct")), device.i_product)?;
+    fs::write(gadget.join(Path::new("iSerial")), device.i_serial_number)?;
+    fs::write(gadget.join(Path::new("functions")), "ffs")?;
+    fs::write(gadget.join(Path::new("f_ffs/aliases")), "ffs")?;
+    fs::write(gadget.join(Path::new("enable")), "1")
+}
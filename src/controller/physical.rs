@@ -1,10 +1,21 @@
-use std::fs::File;
-use std::io::Result;
-use std::io::Write;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::thread::sleep;
 use std::time::{Duration, Instant};
 
-use evdev::Device;
-use evdev::Key;
+use evdev::{AttributeSet, Key};
+
+mod device;
+mod keyboard;
+mod replay;
+
+use device::DeviceInput;
+use keyboard::KeyboardInput;
+use replay::ReplayInput;
+
+use crate::config;
+use crate::error::{Error, Result};
 
 #[derive(Default)]
 pub struct ControllerState {
@@ -28,7 +39,29 @@ pub struct ControllerState {
     pub reverser: u8,
 }
 
-const HOLD_DELAY: Duration = Duration::from_millis(750);
+impl ControllerState {
+    /// Release every control, leaving the power handle at 0 and the emergency brake applied.
+    pub fn set_neutral(&mut self) {
+        *self = ControllerState {
+            brake: 9,
+            ..Default::default()
+        };
+    }
+}
+
+const REOPEN_DELAY: Duration = Duration::from_millis(100);
+const REOPEN_DELAY_MAX: Duration = Duration::from_secs(5);
+
+const PULSE: Duration = Duration::from_millis(150);
+const LONG_PULSE: Duration = Duration::from_millis(500);
+
+/// Feedback code played on the rumble motor and the door lamp.
+pub struct Pattern {
+    /// Short rumble pulses after the initial long one.
+    pub pulses: u32,
+    /// Door lamp blinks after the rumble pulses.
+    pub blinks: u32,
+}
 
 const USED_KEYS: [Key; 26] = [
     Key::KEY_0,
@@ -59,107 +92,100 @@ const USED_KEYS: [Key; 26] = [
     Key::KEY_RIGHT,
 ];
 
-pub fn init() -> Result<[Device; 2]> {
-    let d1 = Device::open("/dev/input/event1")?;
-    let d2 = Device::open("/dev/input/event2")?;
-    Ok([d1, d2])
+/// A source of physical controller input.
+pub trait InputSource {
+    /// Block until new input is available or the timeout expires.
+    fn wait(&mut self, timeout: Option<Duration>);
+
+    /// Update the controller state with the current input, failing if the source was lost.
+    fn get_state(&mut self, state: &mut ControllerState) -> Result<()>;
+
+    /// Open the source again after it was lost.
+    fn reopen(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
-pub fn get_state(state: &mut ControllerState, dev: &[Device; 2]) {
-    for d in dev {
-        if let Ok(key_vals) = d.get_key_state() {
-            for key in USED_KEYS {
-                if d.supported_keys().map_or(true, |k| k.contains(key)) {
-                    read_input(state, key, key_vals.contains(key));
-                }
-            }
+/// Open the input source selected by the `DDGO_PNP_INPUT` environment variable.
+///
+/// Accepted values are `device` (default), `keyboard` and `replay:<file>`. Devices are
+/// read from `inputs`, or discovered in /dev/input if there are none.
+pub fn init(inputs: &[String]) -> Result<Box<dyn InputSource>> {
+    let spec = env::var("DDGO_PNP_INPUT").unwrap_or_default();
+    match spec.as_str() {
+        "" | "device" if inputs.is_empty() => Ok(Box::new(DeviceInput::discover()?)),
+        "" | "device" => Ok(Box::new(DeviceInput::open(inputs)?)),
+        "keyboard" => Ok(Box::new(KeyboardInput::new())),
+        _ => match spec.strip_prefix("replay:") {
+            Some(path) => Ok(Box::new(ReplayInput::open(path)?)),
+            None => Err(Error::Config(format!("unknown input source '{}'", spec))),
+        },
+    }
+}
+
+/// Undo changes made by the input source to the terminal, for when it cannot be dropped.
+pub fn restore_input() {
+    keyboard::restore_terminal();
+}
+
+/// Update the state from the source, waiting for it to come back if it was lost.
+pub fn read_state(dev: &mut dyn InputSource, state: &mut ControllerState) {
+    while let Err(e) = dev.get_state(state) {
+        warn!("{}", e);
+        reopen_input(dev);
+        info!("Input devices are back.");
+    }
+}
+
+/// Try to open the input source again until it succeeds, waiting longer after each failure.
+pub fn reopen_input(dev: &mut dyn InputSource) {
+    let mut delay = REOPEN_DELAY;
+    loop {
+        sleep(delay);
+        match dev.reopen() {
+            Ok(()) => return,
+            Err(e) => debug!("{}", e),
         }
+        delay = (delay * 2).min(REOPEN_DELAY_MAX);
+    }
+}
+
+/// Time left until SELECT counts as held, if it is being pressed.
+pub fn hold_timeout(state: &ControllerState) -> Option<Duration> {
+    match state.button_select_time {
+        Some(time) if !state.button_select_hold && !state.combo => {
+            Some(config::get().hold_delay.saturating_sub(time.elapsed()) + Duration::from_millis(1))
+        }
+        _ => None,
+    }
+}
+
+/// Update the state from the pressed keys of a source without per-key support information.
+fn apply_keys(state: &mut ControllerState, pressed: &AttributeSet<Key>) {
+    for key in USED_KEYS {
+        read_input(state, key, pressed.contains(key));
     }
 }
 
 fn read_input(controller: &mut ControllerState, key: Key, value: bool) {
     // Save input status to object for processing
     match key {
-        Key::KEY_0 => {
-            if value {
-                controller.power = 0
-            }
-        }
-        Key::KEY_1 => {
-            if value {
-                controller.power = 1
-            }
-        }
-        Key::KEY_2 => {
-            if value {
-                controller.power = 2
-            }
-        }
-        Key::KEY_3 => {
-            if value {
-                controller.power = 3
-            }
-        }
-        Key::KEY_4 => {
-            if value {
-                controller.power = 4
-            }
-        }
-        Key::KEY_5 => {
-            if value {
-                controller.power = 5
-            }
-        }
-        Key::KEY_B => {
-            if value {
-                controller.brake = 0
-            }
-        }
-        Key::KEY_C => {
-            if value {
-                controller.brake = 1
-            }
-        }
-        Key::KEY_D => {
-            if value {
-                controller.brake = 2
-            }
-        }
-        Key::KEY_E => {
-            if value {
-                controller.brake = 3
-            }
-        }
-        Key::KEY_F => {
-            if value {
-                controller.brake = 4
-            }
-        }
-        Key::KEY_G => {
-            if value {
-                controller.brake = 5
-            }
-        }
-        Key::KEY_H => {
-            if value {
-                controller.brake = 6
-            }
-        }
-        Key::KEY_I => {
-            if value {
-                controller.brake = 7
-            }
-        }
-        Key::KEY_J => {
-            if value {
-                controller.brake = 8
-            }
-        }
-        Key::KEY_P => {
-            if value {
-                controller.brake = 9
-            }
-        }
+        Key::KEY_0 if value => controller.power = 0,
+        Key::KEY_1 if value => controller.power = 1,
+        Key::KEY_2 if value => controller.power = 2,
+        Key::KEY_3 if value => controller.power = 3,
+        Key::KEY_4 if value => controller.power = 4,
+        Key::KEY_5 if value => controller.power = 5,
+        Key::KEY_B if value => controller.brake = 0,
+        Key::KEY_C if value => controller.brake = 1,
+        Key::KEY_D if value => controller.brake = 2,
+        Key::KEY_E if value => controller.brake = 3,
+        Key::KEY_F if value => controller.brake = 4,
+        Key::KEY_G if value => controller.brake = 5,
+        Key::KEY_H if value => controller.brake = 6,
+        Key::KEY_I if value => controller.brake = 7,
+        Key::KEY_J if value => controller.brake = 8,
+        Key::KEY_P if value => controller.brake = 9,
         Key::KEY_SPACE => {
             if !controller.button_select && value {
                 controller.button_select_time = Some(Instant::now());
@@ -170,7 +196,8 @@ fn read_input(controller: &mut ControllerState, key: Key, value: bool) {
             controller.button_select = value;
             controller.button_select_hold = value;
             if let Some(time) = controller.button_select_time {
-                controller.button_select_hold = time.elapsed() > HOLD_DELAY && !controller.combo;
+                controller.button_select_hold =
+                    time.elapsed() > config::get().hold_delay && !controller.combo;
             }
         }
         Key::KEY_ENTER => controller.button_start = value,
@@ -186,20 +213,63 @@ fn read_input(controller: &mut ControllerState, key: Key, value: bool) {
     }
 }
 
-pub fn set_lamp(status: bool) {
-    if let Ok(mut out) = File::create("/sys/class/leds/led2/brightness") {
-        out.write(if status { b"1" } else { b"0" }).ok();
+pub fn set_lamp(status: bool) -> Result<()> {
+    set_led(&config::get().lamp_led, status)
+}
+
+pub fn set_rumble(status: bool) -> Result<()> {
+    set_led(&config::get().rumble_led, status)
+}
+
+fn set_led(led: &str, status: bool) -> Result<()> {
+    fs::write(
+        Path::new(led).join("brightness"),
+        if status { "1" } else { "0" },
+    )
+    .map_err(|e| Error::Led(led.to_string(), e))
+}
+
+/// Vibrate in short pulses, telling selection problems apart from confirmations.
+pub fn pulse_rumble(pulses: u32) {
+    for _ in 0..pulses {
+        set_rumble(true).ok();
+        sleep(PULSE);
+        set_rumble(false).ok();
+        sleep(PULSE);
     }
-    /*else {
-        println!("WARNING: Could not set door lamp status!")
-    }*/
 }
 
-pub fn set_rumble(status: bool) {
-    if let Ok(mut out) = File::create("/sys/class/leds/led1/brightness") {
-        out.write(if status { b"1" } else { b"0" }).ok();
+/// Play a confirmation: one long vibration, the short pulses, then the lamp blinks.
+pub fn play_pattern(pattern: &Pattern) {
+    if let Err(e) = set_rumble(true) {
+        warn!("{}", e);
+    }
+    sleep(LONG_PULSE);
+    set_rumble(false).ok();
+    sleep(LONG_PULSE);
+    pulse_rumble(pattern.pulses);
+    for _ in 0..pattern.blinks {
+        set_lamp(true).ok();
+        sleep(PULSE);
+        set_lamp(false).ok();
+        sleep(PULSE);
     }
-    /*else {
-        println!("WARNING: Could not set rumble motor status!")
-    }*/
+}
+
+/// Blink the door lamp to indicate an error, like the installation script does.
+pub fn signal_error() {
+    blink_lamp("100");
+}
+
+/// Blink the door lamp slowly to indicate the gadget could not be set up.
+pub fn signal_gadget_error() {
+    blink_lamp("500");
+}
+
+/// Let the LED timer trigger blink the door lamp until it is set again.
+fn blink_lamp(delay: &str) {
+    let lamp = Path::new(&config::get().lamp_led);
+    fs::write(lamp.join("trigger"), "timer").ok();
+    fs::write(lamp.join("delay_on"), delay).ok();
+    fs::write(lamp.join("delay_off"), delay).ok();
 }
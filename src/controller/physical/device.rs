@@ -0,0 +1,236 @@
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use evdev::raw_stream::RawDevice;
+use evdev::{AttributeSet, InputEventKind, Key, Synchronization};
+use nix::fcntl::{FcntlArg, OFlag, fcntl};
+use nix::poll::{PollFd, PollFlags, poll};
+
+use crate::controller::physical::{ControllerState, InputSource, USED_KEYS, read_input};
+use crate::error::{Error, Result};
+
+const INPUT_DIR: &str = "/dev/input";
+
+/// Names of devices known to carry the controls, whatever keys they report.
+const KNOWN_DEVICES: [&str; 2] = ["gpio-keys-polled", "gpio-keys"];
+
+/// Keys of the power and brake handles, the rest of the used keys being buttons.
+const HANDLE_KEYS: [Key; 16] = [
+    Key::KEY_0,
+    Key::KEY_1,
+    Key::KEY_2,
+    Key::KEY_3,
+    Key::KEY_4,
+    Key::KEY_5,
+    Key::KEY_B,
+    Key::KEY_C,
+    Key::KEY_D,
+    Key::KEY_E,
+    Key::KEY_F,
+    Key::KEY_G,
+    Key::KEY_H,
+    Key::KEY_I,
+    Key::KEY_J,
+    Key::KEY_P,
+];
+
+struct InputDevice {
+    path: String,
+    device: RawDevice,
+    keys: Vec<Key>,
+    pressed: AttributeSet<Key>,
+}
+
+impl InputDevice {
+    fn open(path: &str) -> io::Result<InputDevice> {
+        let device = RawDevice::open(path)?;
+        fcntl(device.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        let keys = USED_KEYS
+            .into_iter()
+            .filter(|&key| device.supported_keys().is_none_or(|k| k.contains(key)))
+            .collect();
+        let pressed = device.get_key_state()?;
+        Ok(InputDevice {
+            path: String::from(path),
+            device,
+            keys,
+            pressed,
+        })
+    }
+
+    /// Whether the device reports any controller key or is a known controller device.
+    fn is_controller(&self) -> bool {
+        let keys = self
+            .device
+            .supported_keys()
+            .is_some_and(|keys| USED_KEYS.iter().any(|&key| keys.contains(key)));
+        keys || self
+            .device
+            .name()
+            .is_some_and(|name| KNOWN_DEVICES.contains(&name))
+    }
+
+    /// Name of the device, or its path if it has none, to find it again once lost.
+    fn name(&self) -> String {
+        String::from(self.device.name().unwrap_or(&self.path))
+    }
+
+    /// Log which controls the device provides.
+    fn report(&self) {
+        let handles = self.keys.iter().any(|key| HANDLE_KEYS.contains(key));
+        let buttons = self.keys.iter().any(|key| !HANDLE_KEYS.contains(key));
+        let controls = match (handles, buttons) {
+            (true, true) => "handles and buttons",
+            (true, false) => "handles",
+            (false, true) => "buttons",
+            (false, false) => "no controls",
+        };
+        info!(
+            "Input {} ({}) provides {}.",
+            self.path,
+            self.device.name().unwrap_or("unnamed"),
+            controls
+        );
+    }
+
+    fn fetch_events(&mut self) -> io::Result<()> {
+        let mut dropped = false;
+        loop {
+            let events = match self.device.fetch_events() {
+                Ok(events) => events,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            };
+            for event in events {
+                match event.kind() {
+                    InputEventKind::Key(key) if event.value() == 0 => self.pressed.remove(key),
+                    InputEventKind::Key(key) => self.pressed.insert(key),
+                    InputEventKind::Synchronization(Synchronization::SYN_DROPPED) => dropped = true,
+                    _ => (),
+                }
+            }
+        }
+        // Events were lost, read the whole key state again
+        if dropped {
+            self.pressed = self.device.get_key_state()?;
+        }
+        Ok(())
+    }
+}
+
+/// Input from the Plug & Play evdev devices.
+pub struct DeviceInput {
+    devices: Vec<InputDevice>,
+    /// Devices given by path, `None` if they were discovered.
+    paths: Option<Vec<String>>,
+    /// Names of the discovered devices, which must all be back to reopen the input.
+    names: Vec<String>,
+}
+
+impl DeviceInput {
+    pub fn open(paths: &[String]) -> Result<DeviceInput> {
+        let mut devices = Vec::new();
+        for path in paths {
+            let device = InputDevice::open(path).map_err(|e| Error::InputOpen(path.clone(), e))?;
+            device.report();
+            devices.push(device);
+        }
+        Ok(DeviceInput {
+            devices,
+            paths: Some(paths.to_vec()),
+            names: Vec::new(),
+        })
+    }
+
+    /// Open every device in /dev/input that provides controller keys.
+    pub fn discover() -> Result<DeviceInput> {
+        let devices = find_devices()?;
+        for device in &devices {
+            device.report();
+        }
+        Ok(DeviceInput {
+            names: devices.iter().map(InputDevice::name).collect(),
+            devices,
+            paths: None,
+        })
+    }
+}
+
+/// Open the devices in /dev/input that provide controller keys.
+fn find_devices() -> Result<Vec<InputDevice>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(INPUT_DIR)
+        .map_err(|e| Error::InputOpen(String::from(INPUT_DIR), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("event"))
+        })
+        .collect();
+    paths.sort();
+
+    let mut devices = Vec::new();
+    for path in paths {
+        let path = path.to_string_lossy();
+        match InputDevice::open(&path) {
+            Ok(device) if device.is_controller() => devices.push(device),
+            Ok(_) => debug!("Input {} has no controller keys, skipping.", path),
+            Err(e) => debug!("Could not open input {}, skipping: {}", path, e),
+        }
+    }
+    if devices.is_empty() {
+        return Err(Error::NoInput);
+    }
+    Ok(devices)
+}
+
+impl InputSource for DeviceInput {
+    fn wait(&mut self, timeout: Option<Duration>) {
+        let mut fds: Vec<PollFd> = self
+            .devices
+            .iter()
+            .map(|d| PollFd::new(d.device.as_raw_fd(), PollFlags::POLLIN))
+            .collect();
+        let timeout = timeout.map_or(-1, |t| t.as_millis().try_into().unwrap_or(i32::MAX));
+        poll(&mut fds, timeout).ok();
+    }
+
+    fn get_state(&mut self, state: &mut ControllerState) -> Result<()> {
+        for d in &mut self.devices {
+            d.fetch_events()
+                .map_err(|e| Error::InputLost(d.path.clone(), e))?;
+            for &key in &d.keys {
+                read_input(state, key, d.pressed.contains(key));
+            }
+        }
+        Ok(())
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        if let Some(paths) = &self.paths {
+            *self = DeviceInput::open(paths)?;
+            return Ok(());
+        }
+
+        // Wait for every discovered device, a partly connected controller misses controls
+        let devices = find_devices()?;
+        let mut found: Vec<String> = devices.iter().map(InputDevice::name).collect();
+        for name in &self.names {
+            match found.iter().position(|f| f == name) {
+                Some(index) => {
+                    found.swap_remove(index);
+                }
+                None => return Err(Error::InputMissing(name.clone())),
+            }
+        }
+        for device in &devices {
+            device.report();
+        }
+        self.devices = devices;
+        Ok(())
+    }
+}
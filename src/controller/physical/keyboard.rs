@@ -0,0 +1,147 @@
+use std::io::{Read, stdin};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, channel};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use evdev::{AttributeSet, Key};
+
+use crate::controller::physical::{ControllerState, InputSource, apply_keys};
+use crate::error::Result;
+
+// Terminals do not report key releases, so a key counts as held until autorepeat stops
+const RELEASE_DELAY: Duration = Duration::from_millis(600);
+
+/// Whether the terminal was switched to byte by byte input and must be restored.
+static RAW_TERMINAL: AtomicBool = AtomicBool::new(false);
+
+/// Input from the keyboard of the controlling terminal, for development on a PC.
+///
+/// Keys match the ones sent by the Plug & Play: 0-5 for power, B-J and P for brake,
+/// A/Z/X/S for the A/B/C/D buttons, space for SELECT, enter for START and arrows for the D-pad.
+pub struct KeyboardInput {
+    keys: Receiver<Key>,
+    held: Vec<(Key, Instant)>,
+}
+
+impl KeyboardInput {
+    pub fn new() -> KeyboardInput {
+        // Read the terminal byte by byte without echo
+        Command::new("stty")
+            .args(["-icanon", "-echo"])
+            .status()
+            .ok();
+        RAW_TERMINAL.store(true, Ordering::Relaxed);
+
+        let (sender, keys) = channel();
+        thread::spawn(move || {
+            let mut escape = Vec::new();
+            for byte in stdin().lock().bytes() {
+                let Ok(byte) = byte else { break };
+                escape.push(byte);
+                let key = match escape.as_slice() {
+                    [0x1B] | [0x1B, b'['] => continue,
+                    [0x1B, b'[', b'A'] => Some(Key::KEY_UP),
+                    [0x1B, b'[', b'B'] => Some(Key::KEY_DOWN),
+                    [0x1B, b'[', b'C'] => Some(Key::KEY_RIGHT),
+                    [0x1B, b'[', b'D'] => Some(Key::KEY_LEFT),
+                    [byte] => map_key(*byte),
+                    _ => None,
+                };
+                escape.clear();
+                if let Some(key) = key
+                    && sender.send(key).is_err()
+                {
+                    break;
+                }
+            }
+        });
+        KeyboardInput {
+            keys,
+            held: Vec::new(),
+        }
+    }
+}
+
+impl Drop for KeyboardInput {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// Turn line buffering and echo back on if the keyboard input turned them off.
+pub fn restore_terminal() {
+    if RAW_TERMINAL.swap(false, Ordering::Relaxed) {
+        Command::new("stty").args(["icanon", "echo"]).status().ok();
+    }
+}
+
+fn map_key(byte: u8) -> Option<Key> {
+    let key = match byte.to_ascii_lowercase() {
+        b'0' => Key::KEY_0,
+        b'1' => Key::KEY_1,
+        b'2' => Key::KEY_2,
+        b'3' => Key::KEY_3,
+        b'4' => Key::KEY_4,
+        b'5' => Key::KEY_5,
+        b'b' => Key::KEY_B,
+        b'c' => Key::KEY_C,
+        b'd' => Key::KEY_D,
+        b'e' => Key::KEY_E,
+        b'f' => Key::KEY_F,
+        b'g' => Key::KEY_G,
+        b'h' => Key::KEY_H,
+        b'i' => Key::KEY_I,
+        b'j' => Key::KEY_J,
+        b'p' => Key::KEY_P,
+        b'a' => Key::KEY_A,
+        b'z' => Key::KEY_Z,
+        b'x' => Key::KEY_X,
+        b's' => Key::KEY_S,
+        b' ' => Key::KEY_SPACE,
+        b'\n' => Key::KEY_ENTER,
+        _ => return None,
+    };
+    Some(key)
+}
+
+impl KeyboardInput {
+    fn press(&mut self, key: Key) {
+        self.held.retain(|(k, _)| *k != key);
+        self.held.push((key, Instant::now()));
+    }
+}
+
+impl InputSource for KeyboardInput {
+    fn wait(&mut self, timeout: Option<Duration>) {
+        let release = self
+            .held
+            .iter()
+            .map(|(_, time)| (*time + RELEASE_DELAY).saturating_duration_since(Instant::now()))
+            .min();
+        let key = match release.into_iter().chain(timeout).min() {
+            Some(timeout) => self.keys.recv_timeout(timeout).ok(),
+            None => self.keys.recv().ok(),
+        };
+        if let Some(key) = key {
+            self.press(key);
+        }
+    }
+
+    fn get_state(&mut self, state: &mut ControllerState) -> Result<()> {
+        let now = Instant::now();
+        while let Ok(key) = self.keys.try_recv() {
+            self.press(key);
+        }
+        self.held
+            .retain(|(_, time)| now.duration_since(*time) < RELEASE_DELAY);
+
+        let mut pressed = AttributeSet::new();
+        for (key, _) in &self.held {
+            pressed.insert(*key);
+        }
+        apply_keys(state, &pressed);
+        Ok(())
+    }
+}
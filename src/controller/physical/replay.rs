@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::str::FromStr;
+use std::thread::{park, sleep};
+use std::time::{Duration, Instant};
+
+use evdev::{AttributeSet, Key};
+
+use crate::controller::physical::{ControllerState, InputSource, apply_keys};
+use crate::error::{Error, Result};
+
+/// Input replayed from a script file.
+///
+/// Each line holds a time in milliseconds since the file was opened, a key name and its value,
+/// e.g. `1500 KEY_SPACE 1`. Empty lines and lines starting with `#` are ignored.
+pub struct ReplayInput {
+    events: VecDeque<(Duration, Key, bool)>,
+    pressed: AttributeSet<Key>,
+    start: Instant,
+}
+
+impl ReplayInput {
+    pub fn open(path: &str) -> Result<ReplayInput> {
+        let script = fs::read_to_string(path).map_err(|e| Error::InputOpen(path.to_string(), e))?;
+        let mut events = Vec::new();
+        for (number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            events.push(parse_event(line).ok_or_else(|| {
+                Error::Config(format!(
+                    "{}:{}: invalid replay event '{}'",
+                    path,
+                    number + 1,
+                    line
+                ))
+            })?);
+        }
+        events.sort_by_key(|(time, _, _)| *time);
+        Ok(ReplayInput {
+            events: events.into(),
+            pressed: AttributeSet::new(),
+            start: Instant::now(),
+        })
+    }
+}
+
+fn parse_event(line: &str) -> Option<(Duration, Key, bool)> {
+    let mut fields = line.split_whitespace();
+    let time = fields.next()?.parse().ok()?;
+    let key = Key::from_str(fields.next()?).ok()?;
+    let value = match fields.next()? {
+        "0" => false,
+        "1" => true,
+        _ => return None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((Duration::from_millis(time), key, value))
+}
+
+impl InputSource for ReplayInput {
+    fn wait(&mut self, timeout: Option<Duration>) {
+        let next = self
+            .events
+            .front()
+            .map(|(time, _, _)| time.saturating_sub(self.start.elapsed()));
+        match (next, timeout) {
+            (Some(next), Some(timeout)) => sleep(next.min(timeout)),
+            (Some(next), None) => sleep(next),
+            (None, Some(timeout)) => sleep(timeout),
+            // Nothing left to replay
+            (None, None) => loop {
+                park();
+            },
+        }
+    }
+
+    fn get_state(&mut self, state: &mut ControllerState) -> Result<()> {
+        let elapsed = self.start.elapsed();
+        while let Some(&(time, key, value)) = self.events.front() {
+            if time > elapsed {
+                break;
+            }
+            if value {
+                self.pressed.insert(key);
+            } else {
+                self.pressed.remove(key);
+            }
+            self.events.pop_front();
+        }
+        apply_keys(state, &self.pressed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_key_events() {
+        assert_eq!(
+            parse_event("1500 KEY_ENTER 1"),
+            Some((Duration::from_millis(1500), Key::KEY_ENTER, true))
+        );
+        assert_eq!(
+            parse_event("  0\tKEY_2 0 "),
+            Some((Duration::ZERO, Key::KEY_2, false))
+        );
+    }
+
+    #[test]
+    fn reject_invalid_events() {
+        for line in [
+            "",
+            "KEY_A 1",
+            "-5 KEY_A 1",
+            "100 KEY_NOPE 1",
+            "100 KEY_A 2",
+            "100 KEY_A",
+            "100 KEY_A 1 extra",
+        ] {
+            assert_eq!(parse_event(line), None, "'{}'", line);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::io;
+
+/// Errors that keep the controller from working.
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid command line.
+    Usage(String),
+    /// Invalid setting.
+    Config(String),
+    /// A runtime facility such as a socket or the signal mask could not be set up.
+    Setup(String),
+    /// An input device or source could not be opened.
+    InputOpen(String, io::Error),
+    /// An open input device stopped working.
+    InputLost(String, io::Error),
+    /// A discovered input device is not back after it was lost.
+    InputMissing(String),
+    /// No input device provides controller keys.
+    NoInput,
+    /// Two models share a selection chord.
+    ChordConflict(&'static str, &'static str),
+    /// The g_ffs kernel module could not be loaded.
+    Modprobe(String),
+    /// FunctionFS could not be mounted.
+    Mount(String),
+    /// The FunctionFS descriptors or strings were rejected.
+    DescriptorWrite(io::Error),
+    /// The Android gadget could not be configured.
+    GadgetEnable(io::Error),
+    /// Data could not be read from an endpoint.
+    EndpointRead(u8, io::Error),
+    /// Data could not be written to an endpoint.
+    EndpointWrite(u8, io::Error),
+    /// A LED could not be set through sysfs.
+    Led(String, io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Whether the error happened while setting up the USB gadget.
+    pub fn is_gadget(&self) -> bool {
+        matches!(
+            self,
+            Error::Modprobe(_)
+                | Error::Mount(_)
+                | Error::DescriptorWrite(_)
+                | Error::GadgetEnable(_)
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(message) => write!(f, "Invalid command line: {}", message),
+            Error::Config(message) => write!(f, "Invalid setting: {}", message),
+            Error::Setup(message) => write!(f, "Could not set up {}", message),
+            Error::InputOpen(path, e) => write!(f, "Could not open input {}: {}", path, e),
+            Error::InputLost(path, e) => write!(f, "Lost input {}: {}", path, e),
+            Error::InputMissing(name) => write!(f, "Input {} is still missing", name),
+            Error::NoInput => write!(f, "No controller input device found"),
+            Error::ChordConflict(a, b) => {
+                write!(f, "Selection chords of {} and {} overlap", a, b)
+            }
+            Error::Modprobe(message) => write!(f, "Could not load g_ffs module: {}", message),
+            Error::Mount(message) => write!(f, "Could not mount FunctionFS: {}", message),
+            Error::DescriptorWrite(e) => write!(f, "Descriptors rejected by EP0: {}", e),
+            Error::GadgetEnable(e) => write!(f, "Could not enable Android gadget: {}", e),
+            Error::EndpointRead(endpoint, e) => {
+                write!(f, "Could not read from EP{}: {}", endpoint, e)
+            }
+            Error::EndpointWrite(endpoint, e) => {
+                write!(f, "Could not write to EP{}: {}", endpoint, e)
+            }
+            Error::Led(path, e) => write!(f, "Could not set LED {}: {}", path, e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use nix::sys::signal::{SigSet, Signal};
+
+use crate::error::{Error, Result};
+
+const LOG_FILE: &str = "/tmp/ddgo-pnp-controller.log";
+const HISTORY_FILE: &str = "/tmp/ddgo-pnp-controller.history";
+const HISTORY_SIZE: usize = 256;
+const SYSLOG_SOCKET: &str = "/dev/log";
+const SYSLOG_FACILITY: u8 = 3; // daemon
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Trace, format_args!($($arg)*)) };
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARNING",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    fn syslog_severity(&self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+
+    fn parse(name: &str) -> Option<Level> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+enum Output {
+    Stdout,
+    Syslog(UnixDatagram),
+    File(File),
+}
+
+struct Logger {
+    level: Level,
+    output: Output,
+    history: VecDeque<String>,
+    start: Option<Instant>,
+}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {
+    level: Level::Info,
+    output: Output::Stdout,
+    history: VecDeque::new(),
+    start: None,
+});
+
+/// Set up logging from the `DDGO_PNP_LOG` and `DDGO_PNP_LOG_LEVEL` environment variables.
+///
+/// The output can be `stdout` (default), `syslog`, `file` or `file:<path>`, and the level
+/// one of `error`, `warn`, `info` (default), `debug` or `trace`. Sending SIGUSR1 to the
+/// daemon dumps the most recent messages of any level to a history file.
+pub fn init() -> Result<()> {
+    let level = match env::var("DDGO_PNP_LOG_LEVEL") {
+        Ok(name) => Level::parse(&name)
+            .ok_or_else(|| Error::Config(format!("unknown log level '{}'", name)))?,
+        Err(_) => Level::Info,
+    };
+    let spec = env::var("DDGO_PNP_LOG").unwrap_or_default();
+    let output = match spec.as_str() {
+        "" | "stdout" => Output::Stdout,
+        "syslog" => Output::Syslog(
+            UnixDatagram::unbound().map_err(|e| Error::Setup(format!("syslog: {}", e)))?,
+        ),
+        "file" => Output::File(open_log(LOG_FILE)?),
+        _ => match spec.strip_prefix("file:") {
+            Some(path) => Output::File(open_log(path)?),
+            None => return Err(Error::Config(format!("unknown log output '{}'", spec))),
+        },
+    };
+    {
+        let mut logger = LOGGER.lock().unwrap();
+        logger.level = level;
+        logger.output = output;
+        logger.start.get_or_insert_with(Instant::now);
+    }
+
+    // Dump the history on SIGUSR1, handled in its own thread as threads spawned later inherit the mask
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGUSR1);
+    signals
+        .thread_block()
+        .map_err(|e| Error::Setup(format!("SIGUSR1: {}", e)))?;
+    thread::spawn(move || {
+        while let Ok(Signal::SIGUSR1) = signals.wait() {
+            match dump_history() {
+                Ok(()) => info!("Log history written to {}", HISTORY_FILE),
+                Err(e) => warn!("Could not write log history: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn open_log(path: &str) -> Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| Error::Config(format!("log file {}: {}", path, e)))
+}
+
+pub fn log(level: Level, args: fmt::Arguments) {
+    let mut guard = LOGGER.lock().unwrap();
+    let logger = &mut *guard;
+    let time = logger.start.get_or_insert_with(Instant::now).elapsed();
+
+    if logger.history.len() == HISTORY_SIZE {
+        logger.history.pop_front();
+    }
+    logger.history.push_back(format!(
+        "[{:5}.{:03}] {}: {}",
+        time.as_secs(),
+        time.subsec_millis(),
+        level.name(),
+        args
+    ));
+
+    if level > logger.level {
+        return;
+    }
+    let written = match &mut logger.output {
+        Output::Stdout => {
+            print_line(level, args);
+            true
+        }
+        Output::Syslog(socket) => {
+            let priority = SYSLOG_FACILITY * 8 + level.syslog_severity();
+            let message = format!("<{}>ddgo-pnp-controller: {}", priority, args);
+            socket.send_to(message.as_bytes(), SYSLOG_SOCKET).is_ok()
+        }
+        Output::File(file) => writeln!(file, "{}", logger.history.back().unwrap()).is_ok(),
+    };
+    // Nowhere else to report the failure
+    if !written {
+        print_line(level, args);
+    }
+}
+
+fn print_line(level: Level, args: fmt::Arguments) {
+    match level {
+        Level::Error | Level::Warn => println!("ddgo-pnp-controller: {}: {}", level.name(), args),
+        _ => println!("ddgo-pnp-controller: {}", args),
+    }
+}
+
+/// Write the most recent messages to the history file.
+pub fn dump_history() -> std::io::Result<()> {
+    let history = LOGGER.lock().unwrap().history.clone();
+    let mut contents = String::new();
+    for line in history {
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+    fs::write(HISTORY_FILE, contents)
+}
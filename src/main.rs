@@ -1,51 +1,296 @@
+#[macro_use]
+mod log;
+mod cli;
+mod config;
 mod controller;
+mod error;
+mod menu;
 
-use std::io::Result;
-use std::process::Command;
+use std::process;
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::thread::sleep;
-use std::time::Duration;
-
-use controller::physical::{set_lamp, set_rumble};
-
-fn main() -> Result<()> {
-    match controller::physical::init() {
-        Ok(dev) => {
-            // Wait 3 seconds and get current state of the controller
-            println!("ddgo-pnp-controller: Press a button to select the controller model...");
-            sleep(Duration::from_secs(3));
-            let mut controller_state = Default::default();
-            controller::physical::get_state(&mut controller_state, &dev);
-
-            // Check selected controller model
-            if let Some(controller_model) = controller::emulated::set_model(&controller_state) {
-                // Stop main game
-                stop_game();
-
-                // Vibrate to end selection mode
-                set_rumble(true);
-                sleep(Duration::from_millis(500));
-                set_rumble(false);
-
-                loop {
-                    // Fetch events from input devices
-                    controller::physical::get_state(&mut controller_state, &dev);
-
-                    // Send input to virtual controller
-                    controller::emulated::set_state(&mut controller_state, &controller_model);
-
-                    // Update lamp and rumble
-                    set_lamp(controller_state.lamp);
-                    set_rumble(controller_state.rumble);
-
-                    // Wait between cycles
-                    sleep(Duration::from_millis(5));
-                }
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{SigSet, Signal};
+
+use controller::emulated::{ControllerModel, Selection, Session};
+use controller::physical::{
+    ControllerState, InputSource, hold_timeout, play_pattern, pulse_rumble, read_state,
+    reopen_input, set_lamp, set_rumble, signal_error, signal_gadget_error,
+};
+use error::{Error, Result};
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        // A mistyped command must not leave the lamp of a running unit blinking
+        Err(e @ Error::Usage(_)) => {
+            error!("{}", e);
+            cli::print_usage();
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            error!("{}", e);
+            if e.is_gadget() {
+                signal_gadget_error();
+            } else {
+                signal_error();
             }
-            return Result::Ok(());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    // Termination signals are waited for in their own thread, block them before any thread starts
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGTERM);
+    signals.add(Signal::SIGINT);
+    signals
+        .thread_block()
+        .map_err(|e| Error::Setup(format!("termination signals: {}", e)))?;
+    log::init()?;
+    let options = cli::parse()?;
+    if options.help {
+        cli::print_usage();
+        return Ok(());
+    }
+    if options.list_models {
+        for model in controller::emulated::models() {
+            println!("{:<20}{}", model.id(), model.name());
         }
-        Err(_e) => println!("ddgo-pnp-controller: ERROR: Could not read input devices! Exiting."),
+        return Ok(());
     }
-    Ok(())
+    config::init()?;
+    controller::emulated::check_chords()?;
+
+    let gadget = controller::gadget::init()?;
+    let inputs = if options.inputs.is_empty() {
+        &config::get().inputs
+    } else {
+        &options.inputs
+    };
+    let mut dev = controller::physical::init(inputs)?;
+    let mut controller_state = Default::default();
+
+    // Clean up the current session when asked to terminate
+    let current: Arc<Mutex<Option<Arc<Session>>>> = Default::default();
+    let signal_current = Arc::clone(&current);
+    thread::spawn(move || {
+        if let Ok(signal) = signals.wait() {
+            info!("Received {}, shutting down.", signal);
+            if let Some(session) = signal_current.lock().unwrap().take() {
+                shutdown(&session);
+            }
+
+            // Destructors do not run on exit, clean up the Plug & Play and the input here
+            set_lamp(false).ok();
+            set_rumble(false).ok();
+            controller::physical::restore_input();
+            process::exit(0);
+        }
+    });
+
+    let mut forced_model = options.model;
+    let mut switching = false;
+    let mut game_stopped = false;
+    loop {
+        // Use the given model or wait for the selection
+        let model = match forced_model.take() {
+            Some(model) => Some(model),
+            None => select_model(dev.as_mut(), &mut controller_state, switching),
+        };
+        if options.dry_run {
+            match model {
+                Some(model) => info!("Dry run, would emulate {}.", model.name()),
+                None => info!("Dry run, would start RNDIS gadget."),
+            }
+            return Ok(());
+        }
+
+        // Check selected controller model
+        let Some(session) = controller::emulated::set_model(model, &gadget)? else {
+            // Stay with the game and wait until a controller is wanted
+            if game_stopped {
+                start_game();
+                game_stopped = false;
+            }
+            info!("Hold SELECT+START or the combination of a controller to switch to it.");
+            forced_model = watch(dev.as_mut(), &mut controller_state);
+            switching = forced_model.is_none();
+            continue;
+        };
+        *current.lock().unwrap() = Some(Arc::clone(&session));
+
+        // Stop main game
+        if options.stop_game && !game_stopped {
+            stop_game();
+            game_stopped = true;
+        }
+
+        // Vibrate and blink the model code to end selection mode
+        play_pattern(session.model().confirmation());
+
+        run_session(dev.as_mut(), &mut controller_state, &session);
+
+        // Tear down the gadget before selecting the new model
+        info!("Switching controller model.");
+        if let Some(session) = current.lock().unwrap().take() {
+            shutdown(&session);
+        }
+        switching = true;
+    }
+}
+
+/// Wait for the selection chord, falling back to the remembered or default model at boot.
+///
+/// When switching at runtime, the lamp stays on until a model chord is held.
+fn select_model(
+    dev: &mut dyn InputSource,
+    state: &mut ControllerState,
+    switching: bool,
+) -> Option<ControllerModel> {
+    if switching {
+        set_lamp(true).ok();
+        pulse_rumble(1);
+        while state.button_select || state.button_start {
+            dev.wait(None);
+            read_state(dev, state);
+        }
+    }
+
+    info!("Press a button to select the controller model...");
+    let model = loop {
+        sleep(config::get().selection_wait);
+        read_state(dev, state);
+        match controller::emulated::selected_model(state) {
+            Selection::Model(model) => break Some(model),
+            Selection::None if switching => (),
+            Selection::None => break controller::emulated::fallback_model(),
+            Selection::Rndis => break None,
+            Selection::Menu => match menu::run(dev, state) {
+                Some(model) => break Some(model),
+                None if switching => info!("Press a button to select the controller model..."),
+                None => break None,
+            },
+            Selection::Ambiguous(names) => {
+                warn!("Ambiguous selection ({}), try again.", names.join(", "));
+                pulse_rumble(3);
+            }
+        }
+    };
+    set_lamp(false).ok();
+    model
+}
+
+/// Forward the input to the session until SELECT and START are held to switch the model.
+fn run_session(dev: &mut dyn InputSource, state: &mut ControllerState, session: &Session) {
+    let mut combo = SwitchCombo::new(config::get().switch_hold);
+    loop {
+        // Fetch events from input devices
+        if let Err(e) = dev.get_state(state) {
+            // Release the controls on the host until the devices are back
+            warn!("{}", e);
+            state.set_neutral();
+            controller::emulated::set_state(state, session);
+            reopen_input(dev);
+            info!("Input devices are back.");
+            continue;
+        }
+
+        // Check for the model switch combo
+        if combo.update(state) {
+            return;
+        }
+
+        // Send input to virtual controller
+        controller::emulated::set_state(state, session);
+
+        // Update lamp and rumble
+        set_lamp(state.lamp).ok();
+        set_rumble(state.rumble).ok();
+
+        // Wait for new input, for SELECT or the switch combo to be held long enough or to resend the report
+        let timeout = hold_timeout(state)
+            .into_iter()
+            .chain(session.report_timeout())
+            .chain(combo.timeout())
+            .min();
+        dev.wait(timeout);
+    }
+}
+
+/// Wait while the game is running until a controller is wanted.
+///
+/// Holding SELECT and START leads to the model selection, holding the combination of a
+/// controller switches to it. Both must be held longer than in controller mode, as the game
+/// also uses the buttons.
+fn watch(dev: &mut dyn InputSource, state: &mut ControllerState) -> Option<ControllerModel> {
+    let hold = config::get().watch_hold;
+    let mut combo = SwitchCombo::new(hold);
+    let mut chord: Option<(ControllerModel, Instant)> = None;
+    loop {
+        read_state(dev, state);
+        if combo.update(state) {
+            return None;
+        }
+
+        // Restart the hold time whenever the held combination changes
+        chord = match (controller::emulated::selected_model(state), chord) {
+            (Selection::Model(model), Some((held, since))) if held == model => Some((model, since)),
+            (Selection::Model(model), _) => Some((model, Instant::now())),
+            _ => None,
+        };
+        if let Some((model, since)) = chord
+            && since.elapsed() >= hold
+        {
+            return Some(model);
+        }
+
+        let timeout = combo
+            .timeout()
+            .into_iter()
+            .chain(chord.map(|(_, since)| hold.saturating_sub(since.elapsed())))
+            .min();
+        dev.wait(timeout);
+    }
+}
+
+/// Tracks how long SELECT and START have been held together.
+struct SwitchCombo {
+    hold: Duration,
+    since: Option<Instant>,
+}
+
+impl SwitchCombo {
+    fn new(hold: Duration) -> SwitchCombo {
+        SwitchCombo { hold, since: None }
+    }
+
+    /// Update with the current state, returning whether the combo has been held long enough.
+    fn update(&mut self, state: &ControllerState) -> bool {
+        if state.button_select && state.button_start {
+            let since = *self.since.get_or_insert_with(Instant::now);
+            since.elapsed() >= self.hold
+        } else {
+            self.since = None;
+            false
+        }
+    }
+
+    /// Time left until the combo has been held long enough, if it is being held.
+    fn timeout(&self) -> Option<Duration> {
+        self.since
+            .map(|since| self.hold.saturating_sub(since.elapsed()))
+    }
+}
+
+/// Leave the host with every control released and tear down the gadget.
+fn shutdown(session: &Session) {
+    controller::emulated::release_controls(session);
+    controller::emulated::close(session);
 }
 
 fn stop_game() {
@@ -54,3 +299,13 @@ fn stop_game() {
         .output()
         .ok();
 }
+
+fn start_game() {
+    // The game keeps running, do not wait for it to close our pipes
+    Command::new("/etc/init.d/S99dgtype3")
+        .arg("start")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok();
+}
@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+
+use crate::controller::emulated::{ControllerModel, models};
+use crate::controller::physical::{ControllerState, InputSource, read_state, set_lamp};
+
+const BLINK: Duration = Duration::from_millis(250);
+const PAUSE: Duration = Duration::from_millis(1250);
+
+/// Let the user pick a model with UP/DOWN and confirm it with START, or cancel with SELECT.
+///
+/// The door lamp blinks the position of the highlighted model in the list, then pauses.
+pub fn run(dev: &mut dyn InputSource, state: &mut ControllerState) -> Option<ControllerModel> {
+    let models: Vec<ControllerModel> = models().collect();
+    let mut index = 0;
+    let mut shown = Instant::now();
+    let mut previous = Buttons::read(state);
+    info!("Menu: {}. {}", index + 1, models[index].name());
+
+    loop {
+        // Blink the lamp and wait until the next change or new input
+        let (lamp, next) = blink(index + 1, shown.elapsed());
+        set_lamp(lamp).ok();
+        dev.wait(Some(next));
+        read_state(dev, state);
+
+        // Act on newly pressed buttons only
+        let buttons = Buttons::read(state);
+        let pressed = buttons.pressed_since(&previous);
+        previous = buttons;
+        if pressed.start {
+            set_lamp(false).ok();
+            return Some(models[index]);
+        }
+        if pressed.select {
+            info!("Menu cancelled.");
+            set_lamp(false).ok();
+            return None;
+        }
+        if pressed.up || pressed.down {
+            index = if pressed.down {
+                (index + 1) % models.len()
+            } else {
+                (index + models.len() - 1) % models.len()
+            };
+            shown = Instant::now();
+            info!("Menu: {}. {}", index + 1, models[index].name());
+        }
+    }
+}
+
+/// Lamp status `elapsed` into blinking `count` times, and the time until it changes.
+fn blink(count: usize, elapsed: Duration) -> (bool, Duration) {
+    let blinks = BLINK * 2 * count as u32;
+    let cycle = blinks + PAUSE;
+    let position = Duration::from_nanos((elapsed.as_nanos() % cycle.as_nanos()) as u64);
+    if position < blinks {
+        let step = position.as_nanos() / BLINK.as_nanos();
+        let next = BLINK * (step as u32 + 1) - position;
+        (step.is_multiple_of(2), next)
+    } else {
+        (false, cycle - position)
+    }
+}
+
+/// Menu buttons, to find out which were pressed since the last input.
+struct Buttons {
+    up: bool,
+    down: bool,
+    start: bool,
+    select: bool,
+}
+
+impl Buttons {
+    fn read(state: &ControllerState) -> Buttons {
+        Buttons {
+            up: state.button_up,
+            down: state.button_down,
+            start: state.button_start,
+            select: state.button_select,
+        }
+    }
+
+    fn pressed_since(&self, previous: &Buttons) -> Buttons {
+        Buttons {
+            up: self.up && !previous.up,
+            down: self.down && !previous.down,
+            start: self.start && !previous.start,
+            select: self.select && !previous.select,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn blink_count_times_then_pause() {
+        // Two blinks last 1 s, followed by the pause
+        assert_eq!(blink(2, ms(0)), (true, ms(250)));
+        assert_eq!(blink(2, ms(100)), (true, ms(150)));
+        assert_eq!(blink(2, ms(250)), (false, ms(250)));
+        assert_eq!(blink(2, ms(600)), (true, ms(150)));
+        assert_eq!(blink(2, ms(750)), (false, ms(250)));
+        assert_eq!(blink(2, ms(1000)), (false, ms(1250)));
+        assert_eq!(blink(2, ms(2000)), (false, ms(250)));
+    }
+
+    #[test]
+    fn blink_repeats_after_pause() {
+        assert_eq!(blink(1, ms(1750)), (true, ms(250)));
+        assert_eq!(blink(1, ms(3600)), (true, ms(150)));
+        assert_eq!(blink(3, ms(2750 + 500)), (true, ms(250)));
+    }
+}